@@ -0,0 +1,409 @@
+//! Arbitrary-precision backend for the construction and verification of quasi magic squares,
+//! for N far above `magic_squares::MAX_N` (hundreds of digits).
+//! Only compiled with the `bigint` feature.
+//!
+//! The squares of the (k*3*p²)² family are built from the square for k = 1 (found by the regular search), then
+//! checked axis by axis. Searching at this scale is only possible when the factorization of the sums of two squares
+//! is known: with the center e = k·e₁ of the scaled square, N - e² = k²·(N₁ - e₁²), so it is enough to factor k.
+
+use num_bigint::{BigInt, BigUint};
+use num_traits::{One, ToPrimitive, Zero};
+use std::fmt::Write;
+
+use crate::magic_squares::{Axis, AXES};
+use crate::{conjecture, factorization, generate_squares};
+
+/// Names of the 9 cells, in row-major order.
+const CELL_NAMES: [&str; 9] = ["a", "b", "c", "d", "e", "f", "g", "h", "i"];
+
+/// `factorize` divides by all odd numbers below this bound, before factoring what remains as a u128.
+const TRIAL_DIVISION_BOUND: u32 = 1 << 16;
+
+/// Returns (true, r) if n is a perfect square (with r = √n), else (false, 0).
+/// Most non-squares are rejected by their residues, without computing a square root.
+pub fn is_perfect_square(n: &BigUint) -> (bool, BigUint) {
+    let residue = (n % generate_squares::SQUARE_RESIDUE_PERIOD)
+        .to_u64()
+        .unwrap();
+    if !generate_squares::is_square_residue(residue) {
+        return (false, BigUint::zero());
+    }
+    let r = n.sqrt();
+    if &r * &r == *n {
+        (true, r)
+    } else {
+        (false, BigUint::zero())
+    }
+}
+
+/// Returns the prime factorization of n as (prime, exponent) pairs sorted by increasing prime, or `None` if n has
+/// a factor above 2^128 once its small prime factors (below `TRIAL_DIVISION_BOUND`) are divided out.
+pub fn factorize(n: &BigUint) -> Option<Vec<(u128, u32)>> {
+    let mut factorization = vec![];
+    if n.is_zero() {
+        return Some(factorization);
+    }
+
+    let mut m = n.clone();
+    let twos = m.trailing_zeros().unwrap_or(0);
+    if twos > 0 {
+        factorization.push((2, twos as u32));
+        m >>= twos;
+    }
+    // m fits in a u128 as soon as its factors below the bound are divided out, or never.
+    let mut p = 3u32;
+    while p < TRIAL_DIVISION_BOUND && m.bits() > 128 {
+        let mut count = 0;
+        while (&m % p).is_zero() {
+            m /= p;
+            count += 1;
+        }
+        if count > 0 {
+            factorization.push((p as u128, count));
+        }
+        p += 2;
+    }
+
+    // The loop stops as soon as m fits, so the rest can still have factors that were already found.
+    for (q, e) in factorization::factorize(m.to_u128()?) {
+        match factorization.iter_mut().find(|(p, _)| *p == q) {
+            Some((_, count)) => *count += e,
+            None => factorization.push((q, e)),
+        }
+    }
+    factorization.sort_unstable();
+    Some(factorization)
+}
+
+/// Product of two Gaussian integers (a + bi)(c + di).
+fn gaussian_mul((a, b): &(BigInt, BigInt), (c, d): &(BigInt, BigInt)) -> (BigInt, BigInt) {
+    (a * c - b * d, a * d + b * c)
+}
+
+/// Return all pairs (x, y) with x <= y such that x² + y² == n, given the prime factorization of n
+/// (see `factorize`). Pairs are sorted by increasing x, and include (0, y) when n is a perfect square.
+/// Same as `generate_squares::find_sum_of_squares_pairs_factored`, with Gaussian integers of any size.
+pub fn find_sum_of_squares_pairs(factorization: &[(u128, u32)]) -> Vec<(BigUint, BigUint)> {
+    let mut representations: Vec<(BigInt, BigInt)> = vec![(BigInt::one(), BigInt::zero())];
+    let mut scale = BigUint::one();
+    for &(p, e) in factorization {
+        if p == 2 {
+            let one_plus_i = (BigInt::one(), BigInt::one());
+            for z in representations.iter_mut() {
+                for _ in 0..e {
+                    *z = gaussian_mul(z, &one_plus_i);
+                }
+            }
+        } else if p % 4 == 3 {
+            if e % 2 == 1 {
+                return Vec::new();
+            }
+            scale *= BigUint::from(p).pow(e / 2);
+        } else {
+            let (x, y) = generate_squares::prime_as_sum_of_two_squares(p);
+            let pi = (BigInt::from(x), BigInt::from(y));
+            let mut pi_powers = vec![(BigInt::one(), BigInt::zero())];
+            for j in 0..e as usize {
+                pi_powers.push(gaussian_mul(&pi_powers[j], &pi));
+            }
+            representations = representations
+                .iter()
+                .flat_map(|z| {
+                    let pi_powers = &pi_powers;
+                    (0..=e as usize).map(move |j| {
+                        let (re, im) = &pi_powers[e as usize - j];
+                        gaussian_mul(&gaussian_mul(z, &pi_powers[j]), &(re.clone(), -im))
+                    })
+                })
+                .collect();
+        }
+    }
+
+    let mut pairs: Vec<(BigUint, BigUint)> = representations
+        .into_iter()
+        .map(|(re, im)| {
+            let (x, y) = (re.magnitude() * &scale, im.magnitude() * &scale);
+            if x <= y {
+                (x, y)
+            } else {
+                (y, x)
+            }
+        })
+        .collect();
+    pairs.sort_unstable();
+    pairs.dedup();
+    pairs
+}
+
+/// A 3x3 quasi (or perfect) magic square of squares with arbitrarily large values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigSolution {
+    pub N: BigUint,
+    /// The (k, p) parameters the square was constructed from, if any.
+    pub kp: Option<(BigUint, u64)>,
+    /// The 9 cells `a..i` in row-major order (the square contains their squares).
+    pub cells: [BigUint; 9],
+}
+
+impl BigSolution {
+    /// Returns every axis whose sum of squares is not equal to N, with its sum.
+    pub fn incorrect_axes(&self) -> Vec<(Axis, BigUint)> {
+        AXES.iter()
+            .filter_map(|&(axis, idx)| {
+                let sum: BigUint = idx.iter().map(|&i| &self.cells[i] * &self.cells[i]).sum();
+                if sum == self.N {
+                    None
+                } else {
+                    Some((axis, sum))
+                }
+            })
+            .collect()
+    }
+
+    /// Verifies the square from scratch: N matches (k, p), all values are distinct and positive,
+    /// and at most one axis is incorrect.
+    pub fn verify(&self) -> Result<(), String> {
+        if let Some((k, p)) = &self.kp {
+            let N = N_from_kp(k, *p).ok_or("p is out of range")?;
+            if N != self.N {
+                return Err("N is not equal to (k*3*p²)²".to_string());
+            }
+        }
+        if self.cells.iter().any(|x| x.is_zero()) {
+            return Err("the square contains 0".to_string());
+        }
+        for i in 0..9 {
+            for j in (i + 1)..9 {
+                if self.cells[i] == self.cells[j] {
+                    return Err(format!("{} and {} are equal", CELL_NAMES[i], CELL_NAMES[j]));
+                }
+            }
+        }
+        let incorrect_axes = self.incorrect_axes();
+        if incorrect_axes.len() > 1 {
+            return Err(format!("{} axes are incorrect", incorrect_axes.len()));
+        }
+        Ok(())
+    }
+
+    /// Formats the square as `key = value` lines, readable by `BigSolution::parse`.
+    /// The axis sums are included as comments, so the square can be checked by hand or by any other tool.
+    pub fn to_verifiable_string(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "# 3x3 magic square of squares: each cell x below stands for x²."
+        )
+        .unwrap();
+        if let Some((k, p)) = &self.kp {
+            writeln!(out, "k = {}", k).unwrap();
+            writeln!(out, "p = {}", p).unwrap();
+        }
+        writeln!(out, "N = {}", self.N).unwrap();
+        for (name, x) in CELL_NAMES.iter().zip(&self.cells) {
+            writeln!(out, "{} = {}", name, x).unwrap();
+        }
+        for (axis, sum) in self.incorrect_axes() {
+            writeln!(out, "# {:?} = {} (instead of N)", axis, sum).unwrap();
+        }
+        out
+    }
+
+    /// Parses a square written by `BigSolution::to_verifiable_string`. Lines starting with `#` are ignored.
+    pub fn parse(text: &str) -> Result<BigSolution, String> {
+        let mut values: Vec<(String, String)> = vec![];
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("invalid line: {}", line))?;
+            values.push((key.trim().to_string(), value.trim().to_string()));
+        }
+        let get = |name: &str| -> Result<Option<BigUint>, String> {
+            match values.iter().find(|(key, _)| key == name) {
+                Some((_, value)) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("invalid value for {}", name)),
+                None => Ok(None),
+            }
+        };
+
+        let N = get("N")?.ok_or("missing N")?;
+        let kp = match (get("k")?, get("p")?) {
+            (Some(k), Some(p)) => {
+                let p = u64::try_from(p).map_err(|_| "p does not fit in a u64")?;
+                Some((k, p))
+            }
+            _ => None,
+        };
+        let mut cells: [BigUint; 9] = Default::default();
+        for (cell, name) in cells.iter_mut().zip(CELL_NAMES) {
+            *cell = get(name)?.ok_or(format!("missing {}", name))?;
+        }
+        Ok(BigSolution { N, kp, cells })
+    }
+}
+
+/// Computes N = (k*3*p²)² = k²·(3*p²)², with (3*p²)² given by `conjecture::N_from_kp`.
+/// Returns `None` if p is out of the range of the regular search.
+pub fn N_from_kp(k: &BigUint, p: u64) -> Option<BigUint> {
+    let base_N = conjecture::N_from_kp(1, p).ok()?;
+    Some(k * k * base_N)
+}
+
+/// Constructs the quasi magic square of squares for N = (k*3*p²)², for an arbitrarily large k,
+/// by scaling the square for k = 1 built by `conjecture::construct_quasi_square`.
+pub fn construct_quasi_square(k: &BigUint, p: u64) -> Option<BigSolution> {
+    let base = conjecture::construct_quasi_square(1, p)?;
+    Some(BigSolution {
+        N: N_from_kp(k, p)?,
+        kp: Some((k.clone(), p)),
+        cells: base.grid().map(|x| k * x),
+    })
+}
+
+/// Searches the quasi magic squares of squares for N = (k*3*p²)² whose center is the center of
+/// `construct_quasi_square(k, p)`, to look for squares that are not k times a square for k = 1.
+/// Returns `None` if there is no square for k = 1, or if k can't be factored (see `factorize`).
+///
+/// As in `magic_squares::is_magic_square`, the pairs (a,i), (b,h) and (d,f) are taken among the pairs of
+/// X = N - e², c and g are computed from the top row and the left column, and at most one axis can be incorrect.
+/// The number of pairs grows with the primes p ≡ 1 (mod 4) of k, and the search is quadratic in that number.
+pub fn find_quasi_squares_with_center(k: &BigUint, p: u64) -> Option<Vec<BigSolution>> {
+    let base = conjecture::construct_quasi_square(1, p)?;
+    let N = N_from_kp(k, p)?;
+    let e = k * base.e;
+
+    // X = N - e² = k²·(N₁ - e₁²), whose factorization is the one of k (doubled) and of the u128 N₁ - e₁².
+    let mut X_factorization = factorize(k)?;
+    for (_, count) in X_factorization.iter_mut() {
+        *count *= 2;
+    }
+    for (q, count) in factorization::factorize(base.N - (base.e as u128) * (base.e as u128)) {
+        match X_factorization.iter_mut().find(|(p, _)| *p == q) {
+            Some((_, c)) => *c += count,
+            None => X_factorization.push((q, count)),
+        }
+    }
+    X_factorization.sort_unstable();
+
+    // Every ordered pair of positive values, with its squares.
+    let pairs: Vec<(BigUint, BigUint, BigUint, BigUint)> =
+        find_sum_of_squares_pairs(&X_factorization)
+            .into_iter()
+            .filter(|(x, _)| !x.is_zero())
+            .flat_map(|(x, y)| [(x.clone(), y.clone()), (y, x)])
+            .map(|(x, y)| {
+                let (x2, y2) = (&x * &x, &y * &y);
+                (x, y, x2, y2)
+            })
+            .collect();
+
+    let mut squares = vec![];
+    for (a, i, a2, _) in &pairs {
+        for (b, h, b2, _) in &pairs {
+            if b == a || b == i || a2 + b2 >= N {
+                continue;
+            }
+            let (is_square, c) = is_perfect_square(&(&N - a2 - b2));
+            if !is_square {
+                continue;
+            }
+            for (d, f, d2, _) in &pairs {
+                if a2 + d2 >= N {
+                    continue;
+                }
+                let (is_square, g) = is_perfect_square(&(&N - a2 - d2));
+                if !is_square {
+                    continue;
+                }
+                let square = BigSolution {
+                    N: N.clone(),
+                    kp: Some((k.clone(), p)),
+                    cells: [a, b, &c, d, &e, f, &g, h, i].map(BigUint::clone),
+                };
+                if square.verify().is_ok() {
+                    squares.push(square);
+                }
+            }
+        }
+    }
+    Some(squares)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_construct_and_verify_huge_quasi_square() {
+        let k: BigUint = "1".repeat(150).parse().unwrap();
+        let square = construct_quasi_square(&k, 7).unwrap();
+        assert!(square.N.to_string().len() > 300);
+        assert_eq!(square.verify(), Ok(()));
+        assert_eq!(square.incorrect_axes().len(), 1);
+
+        let parsed = BigSolution::parse(&square.to_verifiable_string()).unwrap();
+        assert_eq!(parsed, square);
+
+        let mut broken = parsed;
+        broken.cells[0] += 1u32;
+        assert!(broken.verify().is_err());
+    }
+
+    #[test]
+    fn test_is_perfect_square() {
+        let root: BigUint = "7".repeat(100).parse().unwrap();
+        assert_eq!(is_perfect_square(&(&root * &root)), (true, root.clone()));
+        assert!(!is_perfect_square(&(&root * &root + 1u32)).0);
+        assert!(!is_perfect_square(&(&root * &root - 1u32)).0);
+    }
+
+    #[test]
+    fn test_find_sum_of_squares_pairs_matches_u64() {
+        for n in [1u64, 2, 25, 1105, 21609 - 58 * 58, 3 * 3 * 5, 3 * 5] {
+            let expected: Vec<(BigUint, BigUint)> =
+                crate::generate_squares::find_sum_of_squares_pairs(n)
+                    .into_iter()
+                    .map(|(x, y)| (x.into(), y.into()))
+                    .collect();
+            let factorization = factorize(&n.into()).unwrap();
+            assert_eq!(find_sum_of_squares_pairs(&factorization), expected);
+        }
+    }
+
+    #[test]
+    fn test_find_sum_of_squares_pairs_huge() {
+        // 2^301·5^100·7^2·13, with more than 150 digits.
+        let n = BigUint::from(2u32).pow(301) * BigUint::from(5u32).pow(100) * 49u32 * 13u32;
+        let factorization = factorize(&n).unwrap();
+        assert_eq!(factorization, vec![(2, 301), (5, 100), (7, 2), (13, 1)]);
+
+        let pairs = find_sum_of_squares_pairs(&factorization);
+        // The representations of 5^100·13 up to units and conjugation: (100 + 1)·(1 + 1) of them.
+        assert_eq!(pairs.len(), 101);
+        assert!(pairs.iter().all(|(x, y)| x <= y && x * x + y * y == n));
+    }
+
+    #[test]
+    fn test_find_quasi_squares_with_center() {
+        // With k = 5, N - e² has more pairs than for k = 1, but the only squares are the scaled square and
+        // its symmetric variants that keep the incorrect anti-diagonal.
+        let k = BigUint::from(5u32);
+        let scaled = construct_quasi_square(&k, 7).unwrap();
+        let squares = find_quasi_squares_with_center(&k, 7).unwrap();
+        assert_eq!(squares.len(), 4);
+        assert!(squares.contains(&scaled));
+        assert!(squares
+            .iter()
+            .all(|square| square.verify().is_ok() && square.cells[4] == scaled.cells[4]));
+
+        // 10^40 has no prime factor above the trial division bound, so it can be searched.
+        let k = BigUint::from(10u32).pow(40);
+        let squares = find_quasi_squares_with_center(&k, 7).unwrap();
+        assert!(squares.contains(&construct_quasi_square(&k, 7).unwrap()));
+    }
+}
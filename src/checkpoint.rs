@@ -0,0 +1,276 @@
+//! Append-only checkpoint files for long sweeps (`test_n` and `test_kp` with `--checkpoint` or `--resume`),
+//! so that a crash or a Ctrl-C only loses the block of work in progress.
+//!
+//! The file is plain text. The first line describes the run (command, range and options), and a run can only be
+//! resumed from a file with the same description. It is followed by one entry per completed block:
+//!
+//! ```text
+//! block <start> <end>
+//! <one line per record, e.g. a square found in the block>
+//! done
+//! ```
+//!
+//! A block is only complete once its `done` line is written, so a block interrupted while being written is
+//! dropped on resume and computed again. The meaning of `<start>`, `<end>` and of the records is up to the caller.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::magic_squares::Solution;
+
+/// First line of every checkpoint file, followed by the description of the run.
+const FORMAT_LINE: &str = "# magic squares checkpoint v1";
+
+/// A checkpoint file, opened for appending completed blocks.
+pub struct Checkpoint {
+    path: PathBuf,
+    file: File,
+    /// Completed blocks: start -> (end, records), sorted by start.
+    completed: BTreeMap<u64, (u64, Vec<String>)>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parses the completed blocks of a checkpoint written for `description`.
+fn parse(text: &str, description: &str) -> Result<BTreeMap<u64, (u64, Vec<String>)>, String> {
+    let mut lines = text.lines();
+    if lines.next() != Some(FORMAT_LINE) {
+        return Err("not a checkpoint file".to_string());
+    }
+    match lines.next() {
+        Some(line) if line == description => {}
+        Some(line) => {
+            return Err(format!(
+                "it was written for `{}`, not for `{}`",
+                line, description
+            ))
+        }
+        None => return Err("missing description".to_string()),
+    }
+
+    // A write interrupted in the middle of a line leaves an incomplete last line (e.g. `blo` or `block 20`),
+    // without its newline: it is dropped, with its block.
+    let mut lines: Vec<&str> = lines.collect();
+    if !text.ends_with('\n') {
+        lines.pop();
+    }
+
+    let mut completed = BTreeMap::new();
+    let mut current: Option<(u64, u64, Vec<String>)> = None;
+    for (index, &line) in lines.iter().enumerate() {
+        match parse_line(line, &mut current, &mut completed) {
+            Ok(()) => {}
+            // An unparsable last line is also an interrupted write.
+            Err(_) if index + 1 == lines.len() => break,
+            Err(err) => return Err(err),
+        }
+    }
+    // A block without its `done` line was interrupted: it is computed again.
+    Ok(completed)
+}
+
+/// Parses a line of the blocks of a checkpoint: starts a block, completes the current block, or adds a record to it.
+fn parse_line(
+    line: &str,
+    current: &mut Option<(u64, u64, Vec<String>)>,
+    completed: &mut BTreeMap<u64, (u64, Vec<String>)>,
+) -> Result<(), String> {
+    if let Some(range) = line.strip_prefix("block ") {
+        let bounds: Vec<u64> = range
+            .split(' ')
+            .map(|x| x.parse().map_err(|_| format!("invalid line `{}`", line)))
+            .collect::<Result<_, _>>()?;
+        match bounds[..] {
+            [start, end] if start < end => *current = Some((start, end, vec![])),
+            _ => return Err(format!("invalid line `{}`", line)),
+        }
+    } else if line == "done" {
+        let (start, end, records) = current
+            .take()
+            .ok_or_else(|| "`done` outside of a block".to_string())?;
+        completed.insert(start, (end, records));
+    } else {
+        match current.as_mut() {
+            Some((_, _, records)) => records.push(line.to_string()),
+            None => return Err(format!("record `{}` outside of a block", line)),
+        }
+    }
+    Ok(())
+}
+
+impl Checkpoint {
+    /// Creates a new checkpoint file for the run described by `description` (a single line), replacing any
+    /// existing file.
+    pub fn create(path: &Path, description: &str) -> io::Result<Checkpoint> {
+        Self::write_new(path, description, BTreeMap::new())
+    }
+
+    /// Reopens the checkpoint file written by an interrupted run described by `description`.
+    /// Fails if the file was written for another run. An interrupted block is removed from the file.
+    pub fn resume(path: &Path, description: &str) -> io::Result<Checkpoint> {
+        let text = fs::read_to_string(path)?;
+        let completed = parse(&text, description).map_err(|err| {
+            invalid_data(format!("cannot resume from {}: {}", path.display(), err))
+        })?;
+        Self::write_new(path, description, completed)
+    }
+
+    /// Writes the header and the completed blocks to a temporary file, which then replaces `path`.
+    fn write_new(
+        path: &Path,
+        description: &str,
+        completed: BTreeMap<u64, (u64, Vec<String>)>,
+    ) -> io::Result<Checkpoint> {
+        let mut text = format!("{}\n{}\n", FORMAT_LINE, description);
+        for (start, (end, records)) in &completed {
+            text += &format!("block {} {}\n", start, end);
+            for record in records {
+                text += record;
+                text.push('\n');
+            }
+            text += "done\n";
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, text)?;
+        fs::rename(&temporary, path)?;
+
+        Ok(Checkpoint {
+            path: path.to_path_buf(),
+            file: OpenOptions::new().append(true).open(path)?,
+            completed,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the records of the block [start, end) if it was completed (by this run or by a previous one).
+    pub fn completed_block(&self, start: u64, end: u64) -> Option<&[String]> {
+        match self.completed.get(&start) {
+            Some((block_end, records)) if *block_end == end => Some(records),
+            _ => None,
+        }
+    }
+
+    /// Number of completed blocks.
+    pub fn completed_count(&self) -> usize {
+        self.completed.len()
+    }
+
+    /// Records the block [start, end) as completed, with its records (one line each),
+    /// and flushes it to the disk before returning.
+    pub fn complete_block(&mut self, start: u64, end: u64, records: Vec<String>) -> io::Result<()> {
+        debug_assert!(records
+            .iter()
+            .all(|r| !r.contains('\n') && r != "done" && !r.starts_with("block ")));
+        let mut text = format!("block {} {}\n", start, end);
+        for record in &records {
+            text += record;
+            text.push('\n');
+        }
+        text += "done\n";
+        // A single write, so that an interruption leaves at most one incomplete block.
+        self.file.write_all(text.as_bytes())?;
+        self.file.sync_data()?;
+        self.completed.insert(start, (end, records));
+        Ok(())
+    }
+}
+
+/// Formats a square as a record: `solution <N> <a> <b> <c> <d> <e> <f> <g> <h> <i>`.
+pub fn solution_record(solution: &Solution) -> String {
+    let cells: Vec<String> = solution.grid().iter().map(|x| x.to_string()).collect();
+    format!("solution {} {}", solution.N, cells.join(" "))
+}
+
+/// Parses a record written by `solution_record`.
+pub fn parse_solution_record(record: &str) -> io::Result<Solution> {
+    let invalid = || invalid_data(format!("invalid record `{}`", record));
+    let mut fields = record
+        .strip_prefix("solution ")
+        .ok_or_else(invalid)?
+        .split(' ');
+    let N: u128 = fields
+        .next()
+        .and_then(|N| N.parse().ok())
+        .ok_or_else(invalid)?;
+    let cells: Vec<u64> = fields
+        .map(|x| x.parse().map_err(|_| invalid()))
+        .collect::<io::Result<_>>()?;
+    let grid: [u64; 9] = cells.try_into().map_err(|_| invalid())?;
+    Ok(Solution::from_grid(N, grid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume() {
+        let path = std::env::temp_dir().join(format!("msq_checkpoint_{}.txt", std::process::id()));
+
+        let mut checkpoint = Checkpoint::create(&path, "test_n 0 30").unwrap();
+        checkpoint.complete_block(0, 10, vec![]).unwrap();
+        checkpoint
+            .complete_block(10, 20, vec!["solution 12 1 2 3".to_string()])
+            .unwrap();
+        drop(checkpoint);
+        // Simulate an interruption in the middle of the last block.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"block 20 30\nsolution 25 4 5").unwrap();
+        drop(file);
+
+        let checkpoint = Checkpoint::resume(&path, "test_n 0 30").unwrap();
+        assert_eq!(checkpoint.completed_count(), 2);
+        assert_eq!(checkpoint.completed_block(0, 10), Some(&[][..]));
+        assert_eq!(
+            checkpoint.completed_block(10, 20),
+            Some(&["solution 12 1 2 3".to_string()][..])
+        );
+        assert_eq!(checkpoint.completed_block(20, 30), None);
+        // The interrupted block was removed from the file.
+        assert!(!fs::read_to_string(&path).unwrap().contains("block 20 30"));
+
+        // A checkpoint cannot be used for another run.
+        assert!(Checkpoint::resume(&path, "test_n 0 40").is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_resume_after_interrupted_block_header() {
+        let path =
+            std::env::temp_dir().join(format!("msq_checkpoint_header_{}.txt", std::process::id()));
+        for partial in ["blo", "block 20", "block 20 3", "block 20 30\ndo"] {
+            let mut checkpoint = Checkpoint::create(&path, "test_n 0 30").unwrap();
+            checkpoint.complete_block(0, 10, vec![]).unwrap();
+            drop(checkpoint);
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(partial.as_bytes()).unwrap();
+            drop(file);
+
+            let checkpoint = Checkpoint::resume(&path, "test_n 0 30").unwrap();
+            assert_eq!(checkpoint.completed_count(), 1, "partial = {:?}", partial);
+            assert_eq!(checkpoint.completed_block(20, 30), None);
+            assert!(fs::read_to_string(&path).unwrap().ends_with("done\n"));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_solution_record() {
+        let solution = Solution::from_grid(21609, [74, 127, 2, 97, 58, 94, 82, 46, 113]);
+        let record = solution_record(&solution);
+        assert_eq!(record, "solution 21609 74 127 2 97 58 94 82 46 113");
+        let parsed = parse_solution_record(&record).unwrap();
+        assert_eq!(parsed.grid(), solution.grid());
+        assert_eq!(parsed.incorrect_axes, solution.incorrect_axes);
+        assert!(parse_solution_record("solution 21609 74 127").is_err());
+    }
+}
@@ -1,29 +1,477 @@
-use crate::{generate_squares, utils};
+use crate::utils::OutOfRange;
+use num_integer::Integer;
 
-pub fn verify_if_N_matches_conjecture(N: u64) -> Option<(u64, u64)> {
-    // Conjecture: N is of the form (k*3*p²)² with p=6n+1 and p prime. We'll first check if N is a multiple of 9.
+use crate::{factorization, generate_squares, magic_squares, utils};
+
+/// Computes N = (k*3*p²)², or returns `OutOfRange` if it is above the largest N supported by the search.
+pub fn N_from_kp(k: u64, p: u64) -> Result<u128, OutOfRange> {
+    let root = (k as u128)
+        .checked_mul(3)
+        .and_then(|x| x.checked_mul((p as u128).checked_mul(p as u128)?))
+        .ok_or(OutOfRange)?;
+    let N = root.checked_mul(root).ok_or(OutOfRange)?;
+    if N > magic_squares::MAX_N {
+        return Err(OutOfRange);
+    }
+    Ok(N)
+}
+
+/// Constructs the quasi magic square for N = (k*3*p²)² without searching it for every k: the square for k = 1
+/// is found by the search, and its values are multiplied by k (which multiplies every axis sum by k²).
+/// Returns `None` if N is out of range, or if there is no square for k = 1.
+pub fn construct_quasi_square(k: u64, p: u64) -> Option<magic_squares::Solution> {
+    let N = N_from_kp(k, p).ok()?;
+    let base = magic_squares::find_perfect_squares(
+        &magic_squares::SearchOptions::default(),
+        None,
+        N_from_kp(1, p).ok()?,
+    )?;
+    // Every value is at most √N = k*3*p², so it fits in a u64.
+    Some(magic_squares::Solution::from_grid(
+        N,
+        base.grid().map(|x| x * k),
+    ))
+}
+
+/// A decomposition N = (k*3*p²)², with p prime and p ≡ 1 (mod 6).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KpDecomposition {
+    pub k: u64,
+    pub p: u64,
+}
+
+/// Whether N fits the conjecture, i.e. is of the form (k*3*p²)² with p prime and p ≡ 1 (mod 6), and why not.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConjectureVerdict {
+    /// Every decomposition of N, sorted by increasing p (never empty).
+    Fits(Vec<KpDecomposition>),
+    NotMultipleOf9,
+    /// N/9 (given) is not a perfect square.
+    NotSquare {
+        quotient: u128,
+    },
+    /// √(N/9) (given) has no prime factor p ≡ 1 (mod 6) with p² | √(N/9).
+    NoSuitablePrime {
+        root: u64,
+    },
+}
+
+impl ConjectureVerdict {
+    pub fn fits(&self) -> bool {
+        matches!(self, ConjectureVerdict::Fits(_))
+    }
+}
+
+impl std::fmt::Display for ConjectureVerdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConjectureVerdict::Fits(decompositions) => {
+                let pairs: Vec<String> = decompositions
+                    .iter()
+                    .map(|d| format!("p={} and k={}", d.p, d.k))
+                    .collect();
+                write!(
+                    f,
+                    "N is in the form of (k*3*p²)² with {}",
+                    pairs.join(", or ")
+                )
+            }
+            ConjectureVerdict::NotMultipleOf9 => write!(f, "N is not a multiple of 9"),
+            ConjectureVerdict::NotSquare { quotient } => {
+                write!(f, "N/9 = {} is not a perfect square", quotient)
+            }
+            ConjectureVerdict::NoSuitablePrime { root } => write!(
+                f,
+                "√(N/9) = {} is not a multiple of p² for any prime p ≡ 1 (mod 6)",
+                root
+            ),
+        }
+    }
+}
+
+/// Checks N against the conjecture, returning every decomposition N = (k*3*p²)² or the reason why there is none.
+/// With N = 9n², the decompositions are the primes p ≡ 1 (mod 6) whose square divides n, with k = n/p²:
+/// they are read from the factorization of n, so the cost is the one of factoring n.
+pub fn check_conjecture(N: u128) -> ConjectureVerdict {
     if N % 9 != 0 {
-        println!("❌ N is not a multiple of 9");
-        return None;
+        return ConjectureVerdict::NotMultipleOf9;
     }
 
-    // Now we have to check if (N/9) is a square (as N/9==(kp²)²).
-    let (is_ps, n) = generate_squares::is_perfect_square(N / 9);
+    // (N/9) = (kp²)² must be a square.
+    let (is_ps, n) = generate_squares::is_perfect_square_u128(N / 9);
     if !is_ps {
-        println!("❌ N/9 is not a perfect square {}", N / 9);
-        return None;
+        return ConjectureVerdict::NotSquare { quotient: N / 9 };
     }
 
-    // Now, we have N/9 = (kp²)² = k * p² = n
-    // We're going to iterate on primes of the form 6n+1 and check if n is divisible by p². If the divisor is also a square, we'll have find k.
-    for p in 0..n {
-        if utils::is_prime(p) && p % 6 == 1 {
-            if (n % p.pow(2)) == 0 {
-                let k = n / p.pow(2);
-                return Some((p, k));
+    let decompositions: Vec<KpDecomposition> = factorization::factorize(n as u128)
+        .into_iter()
+        .filter(|&(p, exponent)| p % 6 == 1 && exponent >= 2)
+        .map(|(p, _)| KpDecomposition {
+            k: n / (p * p) as u64,
+            p: p as u64,
+        })
+        .collect();
+    if decompositions.is_empty() {
+        return ConjectureVerdict::NoSuitablePrime { root: n };
+    }
+    ConjectureVerdict::Fits(decompositions)
+}
+
+/// A hypothesis about which N have a quasi magic square, usable in both directions:
+/// as a predicate (every square found fits) and as a generator (every fitting N has a square).
+/// The hypotheses compared by the `hypotheses` command are listed by `hypotheses`.
+pub trait Hypothesis: Sync {
+    /// Short name, used in the summary table.
+    fn name(&self) -> &str;
+
+    /// The form of N, for display.
+    fn formula(&self) -> String;
+
+    /// Whether N fits the hypothesis.
+    fn fits(&self, N: u128) -> bool;
+
+    /// Every N in [n_min, n_max) that fits the hypothesis, sorted.
+    fn generate(&self, n_min: u128, n_max: u128) -> Vec<u128> {
+        (n_min..n_max).filter(|&N| self.fits(N)).collect()
+    }
+}
+
+/// N = (m*n)² where n = k * p₁² * ... * p_c² for `prime_count` distinct primes pᵢ ≡ `residue` (mod `modulus`),
+/// with m = `multiplier` and any k ≥ 1. The conjecture is m = 3, a single prime p ≡ 1 (mod 6).
+pub struct PrimeSquareForm {
+    pub name: &'static str,
+    pub multiplier: u64,
+    pub modulus: u64,
+    pub residue: u64,
+    pub prime_count: usize,
+}
+
+impl PrimeSquareForm {
+    /// Whether n = k * p₁² * ... * p_c² for enough primes of the right class.
+    fn fits_root(&self, n: u64) -> bool {
+        factorization::factorize(n as u128)
+            .iter()
+            .filter(|&&(p, exponent)| {
+                p % self.modulus as u128 == self.residue as u128 && exponent >= 2
+            })
+            .count()
+            >= self.prime_count
+    }
+}
+
+impl Hypothesis for PrimeSquareForm {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn formula(&self) -> String {
+        let primes: Vec<String> = (1..=self.prime_count).map(|i| format!("p{}²", i)).collect();
+        let class = if self.modulus == 1 {
+            "prime".to_string()
+        } else {
+            format!("≡ {} (mod {})", self.residue, self.modulus)
+        };
+        format!("({}*k*{})², p {}", self.multiplier, primes.join("*"), class)
+    }
+
+    fn fits(&self, N: u128) -> bool {
+        let m2 = (self.multiplier as u128).pow(2);
+        if N == 0 || N % m2 != 0 {
+            return false;
+        }
+        let (is_ps, n) = generate_squares::is_perfect_square_u128(N / m2);
+        is_ps && self.fits_root(n)
+    }
+
+    /// Only the squares of multiples of m are candidates, so there are about √n_max / m of them to factor.
+    fn generate(&self, n_min: u128, n_max: u128) -> Vec<u128> {
+        let m = self.multiplier as u128;
+        ((n_min.isqrt() / m).max(1)..)
+            .map(|n| (n, (m * n).pow(2)))
+            .skip_while(|&(_, N)| N < n_min)
+            .take_while(|&(_, N)| N < n_max)
+            .filter(|&(n, _)| self.fits_root(n as u64))
+            .map(|(_, N)| N)
+            .collect()
+    }
+}
+
+/// The hypotheses compared by the `hypotheses` command: the conjecture first, then the alternatives.
+pub fn hypotheses() -> Vec<Box<dyn Hypothesis>> {
+    let form = |name, multiplier, modulus, residue, prime_count| -> Box<dyn Hypothesis> {
+        Box::new(PrimeSquareForm {
+            name,
+            multiplier,
+            modulus,
+            residue,
+            prime_count,
+        })
+    };
+    vec![
+        form("kp", 3, 6, 1, 1),
+        // Other prime classes.
+        form("kp_any_prime", 3, 1, 0, 1),
+        form("kp_1_mod_4", 3, 4, 1, 1),
+        // Other multipliers.
+        form("kp_no_3", 1, 6, 1, 1),
+        form("kp_9", 9, 6, 1, 1),
+        // Products of several 6n+1 primes.
+        form("kpq", 3, 6, 1, 2),
+    ]
+}
+
+/// Every n = p₁^e₁ * ... * p_c^e_c made of 1 to `max_primes` distinct primes pᵢ ≡ 1 (mod 6) below `max_p`,
+/// with exponents 1 ≤ eᵢ ≤ `max_exponent`, such that n ≤ `max_n` and N = (3n)² is at most `magic_squares::MAX_N`.
+/// Each n is given by its (prime, exponent) pairs sorted by increasing prime, and the list is sorted by n.
+pub fn products_of_6n1_primes(
+    max_p: u64,
+    max_exponent: u32,
+    max_primes: usize,
+    max_n: u128,
+) -> Vec<Vec<(u64, u32)>> {
+    let primes: Vec<u64> = (7..=max_p)
+        .step_by(6)
+        .filter(|&p| utils::is_prime(p))
+        .collect();
+    let max_n = max_n.min((magic_squares::MAX_N / 9).isqrt());
+
+    // Depth-first: each prime is either skipped or used with one of the exponents, from the smallest prime up.
+    fn extend(
+        primes: &[u64],
+        max_exponent: u32,
+        slots: usize,
+        n: u128,
+        max_n: u128,
+        factors: &mut Vec<(u64, u32)>,
+        products: &mut Vec<(u128, Vec<(u64, u32)>)>,
+    ) {
+        if !factors.is_empty() {
+            products.push((n, factors.clone()));
+        }
+        if slots == 0 {
+            return;
+        }
+        for (index, &p) in primes.iter().enumerate() {
+            let mut power = n;
+            for exponent in 1..=max_exponent {
+                power *= p as u128;
+                if power > max_n {
+                    break;
+                }
+                factors.push((p, exponent));
+                extend(
+                    &primes[index + 1..],
+                    max_exponent,
+                    slots - 1,
+                    power,
+                    max_n,
+                    factors,
+                    products,
+                );
+                factors.pop();
             }
         }
     }
 
-    None
+    let mut products = vec![];
+    extend(
+        &primes,
+        max_exponent,
+        max_primes,
+        1,
+        max_n,
+        &mut vec![],
+        &mut products,
+    );
+    products.sort_unstable_by_key(|(n, _)| *n);
+    products.into_iter().map(|(_, factors)| factors).collect()
+}
+
+/// For a square with N = (3n)², where n has the prime factorization `factors`, returns the primes p ≡ 1 (mod 6)
+/// the square "belongs to": those with n = k*p² where the square is k times a square for (3p²)², i.e. k divides
+/// all of its entries.
+pub fn square_owners(factors: &[(u64, u32)], grid: &[u64; 9]) -> Vec<u64> {
+    let n: u128 = factors
+        .iter()
+        .map(|&(p, exponent)| (p as u128).pow(exponent))
+        .product();
+    let gcd = grid.iter().fold(0u128, |g, &x| g.gcd(&(x as u128)));
+    factors
+        .iter()
+        .filter(|&&(p, exponent)| {
+            p % 6 == 1 && exponent >= 2 && gcd % (n / (p as u128).pow(2)) == 0
+        })
+        .map(|&(p, _)| p)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The scan used before the factorization: every p ≡ 1 (mod 6) up to √n, tested for primality and for p² | n.
+    fn kp_decompositions_by_scanning(n: u64) -> Vec<KpDecomposition> {
+        (1..)
+            .take_while(|&p| p * p <= n)
+            .filter(|&p| utils::is_prime(p) && p % 6 == 1 && n % (p * p) == 0)
+            .map(|p| KpDecomposition { k: n / (p * p), p })
+            .collect()
+    }
+
+    #[test]
+    fn test_N_from_kp_range() {
+        // The largest k with (k*3)² <= MAX_N for p = 1.
+        let k = (magic_squares::MAX_N.isqrt() / 3) as u64;
+        let N = N_from_kp(k, 1).unwrap();
+        assert_eq!(N, (3 * k as u128) * (3 * k as u128));
+        assert!(N > u64::MAX as u128);
+        assert_eq!(N_from_kp(k + 1, 1), Err(OutOfRange));
+        // k*3*p² overflows a u128.
+        assert_eq!(N_from_kp(u64::MAX, u64::MAX), Err(OutOfRange));
+    }
+
+    #[test]
+    fn test_check_conjecture() {
+        assert_eq!(
+            check_conjecture(21609),
+            ConjectureVerdict::Fits(vec![KpDecomposition { k: 1, p: 7 }])
+        );
+        // 7² * 13² has two decompositions, and 7⁴ only one (k = 7²).
+        assert_eq!(
+            check_conjecture(N_from_kp(169, 7).unwrap()),
+            ConjectureVerdict::Fits(vec![
+                KpDecomposition { k: 169, p: 7 },
+                KpDecomposition { k: 49, p: 13 }
+            ])
+        );
+        assert_eq!(
+            check_conjecture(N_from_kp(49, 7).unwrap()),
+            ConjectureVerdict::Fits(vec![KpDecomposition { k: 49, p: 7 }])
+        );
+
+        assert_eq!(check_conjecture(21610), ConjectureVerdict::NotMultipleOf9);
+        assert_eq!(
+            check_conjecture(18),
+            ConjectureVerdict::NotSquare { quotient: 2 }
+        );
+        assert_eq!(
+            check_conjecture(18).to_string(),
+            "N/9 = 2 is not a perfect square"
+        );
+        // 5² and 11² do not count: 5, 11 ≢ 1 (mod 6).
+        assert_eq!(
+            check_conjecture(9 * 275 * 275),
+            ConjectureVerdict::NoSuitablePrime { root: 275 }
+        );
+
+        for n in 1..20_000u64 {
+            let N = 9 * (n as u128) * (n as u128);
+            let decompositions = match check_conjecture(N) {
+                ConjectureVerdict::Fits(decompositions) => decompositions,
+                _ => vec![],
+            };
+            assert_eq!(
+                decompositions,
+                kp_decompositions_by_scanning(n),
+                "n = {}",
+                n
+            );
+            for d in decompositions {
+                assert_eq!(N_from_kp(d.k, d.p), Ok(N));
+            }
+        }
+
+        // Factoring stays fast far beyond the reach of the scan.
+        let p = 1_000_003;
+        assert_eq!(
+            check_conjecture(N_from_kp(2, p).unwrap()),
+            ConjectureVerdict::Fits(vec![KpDecomposition { k: 2, p }])
+        );
+    }
+
+    #[test]
+    fn test_hypotheses() {
+        let hypotheses = hypotheses();
+        let conjecture = &hypotheses[0];
+        for N in (0..2_000_000u128).step_by(9) {
+            assert_eq!(conjecture.fits(N), check_conjecture(N).fits(), "N = {}", N);
+        }
+
+        // The generators list the same N as the predicates.
+        for hypothesis in &hypotheses {
+            let (n_min, n_max) = (20_000, 30_000_000);
+            let expected: Vec<u128> = (1..6_000u128)
+                .map(|n| n * n)
+                .filter(|&N| N >= n_min && N < n_max && hypothesis.fits(N))
+                .collect();
+            assert_eq!(
+                hypothesis.generate(n_min, n_max),
+                expected,
+                "{}",
+                hypothesis.name()
+            );
+        }
+
+        // 21609 = (3*7²)² = (1*9*7²)², but it is not a multiple of 9², has a single prime square factor,
+        // and 7 ≢ 1 (mod 4).
+        let fitting: Vec<&str> = hypotheses
+            .iter()
+            .filter(|h| h.fits(21609))
+            .map(|h| h.name())
+            .collect();
+        assert_eq!(fitting, vec!["kp", "kp_any_prime", "kp_no_3"]);
+    }
+
+    #[test]
+    fn test_construct_quasi_square() {
+        let square = construct_quasi_square(10, 7).unwrap();
+        assert_eq!(square.N, N_from_kp(10, 7).unwrap());
+        assert_eq!(
+            square.grid(),
+            [740, 1270, 20, 970, 580, 940, 820, 460, 1130]
+        );
+        assert_eq!(square.incorrect_axes.len(), 1);
+        assert_eq!(square.solution_type, magic_squares::SolutionType::Partial);
+        // The search finds a square for the same N.
+        assert!(magic_squares::find_perfect_squares(
+            &magic_squares::SearchOptions::default(),
+            None,
+            square.N
+        )
+        .is_some());
+        assert!(construct_quasi_square(u64::MAX, 7).is_none());
+    }
+
+    #[test]
+    fn test_products_of_6n1_primes() {
+        let products = products_of_6n1_primes(13, 2, 2, u128::MAX);
+        let values: Vec<u64> = products
+            .iter()
+            .map(|factors| factors.iter().map(|&(p, e)| p.pow(e)).product())
+            .collect();
+        assert_eq!(values, vec![7, 13, 49, 91, 169, 637, 1183, 8281]);
+        assert_eq!(products[6], vec![(7, 1), (13, 2)]);
+        // N = (3n)² stays in the supported range.
+        assert!(products_of_6n1_primes(200, 30, 3, u128::MAX)
+            .iter()
+            .all(|factors| N_from_kp(factors.iter().map(|&(p, e)| p.pow(e)).product(), 1).is_ok()));
+        // Products above max_n are skipped.
+        assert_eq!(
+            products_of_6n1_primes(13, 2, 2, 1000).len(),
+            values.iter().filter(|&&n| n <= 1000).count()
+        );
+    }
+
+    #[test]
+    fn test_square_owners() {
+        // The square of (3*7²)², scaled by 13² for n = 7² * 13².
+        let grid = [74, 127, 2, 97, 58, 94, 82, 46, 113].map(|x| x * 169);
+        assert_eq!(square_owners(&[(7, 2), (13, 2)], &grid), vec![7]);
+        // With n = 7 * 13², it would have to be 7 times a square for (3*13²)².
+        assert_eq!(square_owners(&[(7, 1), (13, 2)], &grid), vec![]);
+        assert_eq!(
+            square_owners(&[(7, 2)], &[74, 127, 2, 97, 58, 94, 82, 46, 113]),
+            vec![7]
+        );
+    }
 }
@@ -0,0 +1,369 @@
+//! Integer factorization for numbers up to u128: trial division by small primes, then Pollard's rho
+//! (Brent's variant) with a Miller–Rabin primality test.
+//! All the modular arithmetic is done in Montgomery form, so that no 256-bit division is ever needed.
+
+use num_integer::Integer;
+
+/// Numbers are divided by all odd numbers below this bound before Pollard's rho is used.
+const TRIAL_DIVISION_BOUND: u64 = 1 << 10;
+
+/// Miller–Rabin witnesses. The first 13 primes are a deterministic set for every n < 3.3·10^24;
+/// above that, the test is probabilistic, with an error probability below 4^-20.
+const MILLER_RABIN_WITNESSES: [u128; 20] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+];
+
+/// Returns the full 256-bit product a * b, as (high, low) 128-bit halves.
+fn mul_wide(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & MASK);
+    let (b_hi, b_lo) = (b >> 64, b & MASK);
+
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_hi = a_hi * b_hi;
+
+    let middle = (lo_lo >> 64) + (hi_lo & MASK) + (lo_hi & MASK);
+    let low = (middle << 64) | (lo_lo & MASK);
+    let high = hi_hi + (hi_lo >> 64) + (lo_hi >> 64) + (middle >> 64);
+    (high, low)
+}
+
+/// Montgomery arithmetic modulo an odd n, with R = 2^128.
+struct Montgomery {
+    n: u128,
+    /// -n⁻¹ mod R.
+    n_prime: u128,
+    /// R² mod n, used to convert numbers to the Montgomery form.
+    r2: u128,
+}
+
+impl Montgomery {
+    fn new(n: u128) -> Montgomery {
+        debug_assert!(n % 2 == 1);
+        // Newton's iteration: each step doubles the number of correct low bits (n * n ≡ 1 mod 8).
+        let mut inv = n;
+        for _ in 0..7 {
+            inv = inv.wrapping_mul(2u128.wrapping_sub(n.wrapping_mul(inv)));
+        }
+
+        // R mod n, then doubled 128 times to get R² mod n.
+        let mut r2 = (u128::MAX % n + 1) % n;
+        for _ in 0..128 {
+            r2 = Self::add_mod(r2, r2, n);
+        }
+
+        Montgomery {
+            n,
+            n_prime: inv.wrapping_neg(),
+            r2,
+        }
+    }
+
+    fn add_mod(a: u128, b: u128, n: u128) -> u128 {
+        let (sum, overflow) = a.overflowing_add(b);
+        if overflow || sum >= n {
+            sum.wrapping_sub(n)
+        } else {
+            sum
+        }
+    }
+
+    /// Montgomery reduction: returns T / R mod n, for T = (high, low) < n * R.
+    fn reduce(&self, (high, low): (u128, u128)) -> u128 {
+        let m = low.wrapping_mul(self.n_prime);
+        let (mn_high, mn_low) = mul_wide(m, self.n);
+        // low + mn_low ≡ 0 mod R, so the only thing kept from the low halves is the carry.
+        let carry = low.overflowing_add(mn_low).1 as u128;
+        let (t, overflow1) = high.overflowing_add(mn_high);
+        let (t, overflow2) = t.overflowing_add(carry);
+        if overflow1 || overflow2 || t >= self.n {
+            t.wrapping_sub(self.n)
+        } else {
+            t
+        }
+    }
+
+    fn to_montgomery(&self, x: u128) -> u128 {
+        self.reduce(mul_wide(x % self.n, self.r2))
+    }
+
+    fn from_montgomery(&self, x: u128) -> u128 {
+        self.reduce((0, x))
+    }
+
+    fn mul(&self, a: u128, b: u128) -> u128 {
+        self.reduce(mul_wide(a, b))
+    }
+
+    fn add(&self, a: u128, b: u128) -> u128 {
+        Self::add_mod(a, b, self.n)
+    }
+
+    fn pow(&self, base: u128, mut exponent: u128) -> u128 {
+        let mut result = self.to_montgomery(1);
+        let mut base = base;
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = self.mul(result, base);
+            }
+            base = self.mul(base, base);
+            exponent >>= 1;
+        }
+        result
+    }
+}
+
+/// Returns (base^exponent) mod n.
+pub fn pow_mod(base: u128, exponent: u128, n: u128) -> u128 {
+    if n == 1 {
+        return 0;
+    }
+    if n % 2 == 0 {
+        // Only used with odd moduli in this crate, but kept correct for any n.
+        let mut result = 1u128;
+        let mut base = base % n;
+        let mut exponent = exponent;
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = mul_mod_slow(result, base, n);
+            }
+            base = mul_mod_slow(base, base, n);
+            exponent >>= 1;
+        }
+        return result;
+    }
+    let mont = Montgomery::new(n);
+    mont.from_montgomery(mont.pow(mont.to_montgomery(base), exponent))
+}
+
+/// (a * b) mod n by double-and-add, for the rare even moduli.
+fn mul_mod_slow(a: u128, b: u128, n: u128) -> u128 {
+    let mut result = 0;
+    let mut a = a % n;
+    let mut b = b;
+    while b > 0 {
+        if b & 1 == 1 {
+            result = Montgomery::add_mod(result, a, n);
+        }
+        a = Montgomery::add_mod(a, a, n);
+        b >>= 1;
+    }
+    result
+}
+
+/// Miller–Rabin primality test (see `MILLER_RABIN_WITNESSES` for the guarantees).
+pub fn is_probable_prime(n: u128) -> bool {
+    if n < 2 {
+        return false;
+    }
+    for &p in MILLER_RABIN_WITNESSES.iter() {
+        if n % p == 0 {
+            return n == p;
+        }
+    }
+
+    let mont = Montgomery::new(n);
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    let one = mont.to_montgomery(1);
+    let minus_one = mont.to_montgomery(n - 1);
+
+    let witnesses = if n < 3_317_044_064_679_887_385_961_981 {
+        &MILLER_RABIN_WITNESSES[..13]
+    } else {
+        &MILLER_RABIN_WITNESSES[..]
+    };
+    'witness: for &a in witnesses {
+        let mut x = mont.pow(mont.to_montgomery(a), d);
+        if x == one || x == minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = mont.mul(x, x);
+            if x == minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+/// Finds a non-trivial divisor of the odd composite n with Pollard's rho (Brent's variant).
+/// The differences are multiplied together in batches, so that only one gcd is computed per batch.
+fn pollard_rho(n: u128) -> u128 {
+    const BATCH: usize = 128;
+    let mont = Montgomery::new(n);
+
+    for c in 1u128.. {
+        let c = mont.to_montgomery(c);
+        let f = |x: u128| mont.add(mont.mul(x, x), c);
+
+        let mut y = mont.to_montgomery(2);
+        let mut x = y;
+        let mut saved_y = y;
+        let mut q = mont.to_montgomery(1);
+        let mut g = 1;
+        let mut r = 1usize;
+
+        while g == 1 {
+            x = y;
+            for _ in 0..r {
+                y = f(y);
+            }
+            let mut k = 0;
+            while k < r && g == 1 {
+                saved_y = y;
+                for _ in 0..BATCH.min(r - k) {
+                    y = f(y);
+                    q = mont.mul(q, x.abs_diff(y));
+                }
+                g = q.gcd(&n);
+                k += BATCH;
+            }
+            r *= 2;
+        }
+
+        if g == n {
+            // The batch overshot: step through it one difference at a time.
+            loop {
+                saved_y = f(saved_y);
+                g = x.abs_diff(saved_y).gcd(&n);
+                if g > 1 {
+                    break;
+                }
+            }
+        }
+        if g != n {
+            return g;
+        }
+        // Failure for this constant: try the next one.
+    }
+    unreachable!()
+}
+
+/// Pushes the prime factors of n (with multiplicity, in no particular order).
+fn push_prime_factors(n: u128, factors: &mut Vec<u128>) {
+    if n == 1 {
+        return;
+    }
+    if is_probable_prime(n) {
+        factors.push(n);
+        return;
+    }
+    let d = pollard_rho(n);
+    push_prime_factors(d, factors);
+    push_prime_factors(n / d, factors);
+}
+
+/// Divides m by p as many times as possible and returns the number of divisions.
+/// The u64 division is much cheaper than the u128 one, so it is used as soon as m fits.
+fn divide_out(m: &mut u128, p: u64) -> u32 {
+    let mut count = 0;
+    match u64::try_from(*m) {
+        Ok(mut small) => {
+            while small % p == 0 {
+                small /= p;
+                count += 1;
+            }
+            *m = small as u128;
+        }
+        Err(_) => {
+            while *m % p as u128 == 0 {
+                *m /= p as u128;
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// Returns the prime factorization of n as (prime, exponent) pairs sorted by increasing prime.
+/// The factorization of 0 and 1 is empty.
+pub fn factorize(n: u128) -> Vec<(u128, u32)> {
+    let mut factorization = vec![];
+    if n == 0 {
+        return factorization;
+    }
+
+    let mut m = n;
+    let twos = m.trailing_zeros();
+    if twos > 0 {
+        factorization.push((2, twos));
+        m >>= twos;
+    }
+    let mut p = 3u64;
+    while p < TRIAL_DIVISION_BOUND && (p as u128) * (p as u128) <= m {
+        let count = divide_out(&mut m, p);
+        if count > 0 {
+            factorization.push((p as u128, count));
+        }
+        p += 2;
+    }
+
+    if (p as u128) * (p as u128) > m {
+        // m has no factor up to its square root: it is 1 or a prime larger than all the previous ones.
+        if m > 1 {
+            factorization.push((m, 1));
+        }
+        return factorization;
+    }
+
+    // What remains has no factor below TRIAL_DIVISION_BOUND.
+    let mut factors = vec![];
+    push_prime_factors(m, &mut factors);
+    factors.sort_unstable();
+    for f in factors {
+        match factorization.last_mut() {
+            Some((p, count)) if *p == f => *count += 1,
+            _ => factorization.push((f, 1)),
+        }
+    }
+    factorization
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_factorize() {
+        assert_eq!(factorize(1), vec![]);
+        assert_eq!(factorize(2), vec![(2, 1)]);
+        assert_eq!(factorize(360), vec![(2, 3), (3, 2), (5, 1)]);
+        for n in 1..5_000u128 {
+            let product: u128 = factorize(n).iter().map(|&(p, e)| p.pow(e)).product();
+            assert_eq!(product, n);
+        }
+
+        // Product of a 13-digit prime and of a prime above 2^62 (Pollard's rho needs ~10^6 steps).
+        let (p, q) = (1_000_000_000_039u128, 4_611_686_018_427_388_039u128);
+        assert_eq!(factorize(p * q), vec![(p, 1), (q, 1)]);
+        assert_eq!(factorize(p * p * 3), vec![(3, 1), (p, 2)]);
+    }
+
+    #[test]
+    fn test_is_probable_prime() {
+        let primes: Vec<u128> = (2..2_000u128)
+            .filter(|&n| (2..n).take_while(|d| d * d <= n).all(|d| n % d != 0))
+            .collect();
+        for n in 0..2_000u128 {
+            assert_eq!(is_probable_prime(n), primes.contains(&n), "n = {}", n);
+        }
+        // Strong pseudoprime to all the bases from 2 to 37.
+        assert!(!is_probable_prime(318_665_857_834_031_151_167_461));
+        // 2^127 - 1 is a Mersenne prime.
+        assert!(is_probable_prime((1u128 << 127) - 1));
+    }
+
+    #[test]
+    fn test_pow_mod() {
+        // Fermat's little theorem, with a prime above 2^64.
+        let p = 18_446_744_073_709_551_629u128;
+        assert_eq!(pow_mod(3, p - 1, p), 1);
+        assert_eq!(pow_mod(2, 10, 1000), 24);
+        assert_eq!(pow_mod(5, 0, 7), 1);
+    }
+}
@@ -1,9 +1,16 @@
 use fxhash::FxHashMap;
-use std::time::Instant;
+use std::ops::Range;
 
 use num_cpus;
 use rayon::prelude::*;
 
+use crate::factorization;
+use crate::utils::OutOfRange;
+
+/// Above this value, `find_sum_of_squares_pairs` factors n instead of scanning all x up to √(n/2).
+/// (The scan is faster for small n, the factorization is faster above ~10^8.)
+const FACTORIZATION_THRESHOLD: u64 = 1 << 27;
+
 /// Return true if `n` has a prime factor p ≡ 3 (mod 4) with an odd exponent.
 fn has_forbidden_prime_factor(n: u64) -> bool {
     if n == 0 {
@@ -38,9 +45,58 @@ fn can_be_sum_of_two_squares(n: u64) -> bool {
     !has_forbidden_prime_factor(n)
 }
 
-/// Return all pairs (x, y) such that x² + y² == n using a two-pointers approach.
+/// Whether zero is an allowed value for the entries of a square.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ZeroPolicy {
+    /// All entries are strictly positive (0 is excluded).
+    #[default]
+    StrictlyPositive,
+    /// Entries can be 0.
+    NonNegative,
+}
+
+impl ZeroPolicy {
+    /// Returns true if `x` is an allowed entry under this policy.
+    pub fn allows(&self, x: u64) -> bool {
+        x > 0 || *self == ZeroPolicy::NonNegative
+    }
+}
+
+/// A pair (x, y) with x² + y² = n: computed on 64 bits, or read from a `SquareSumTable` on 32 bits.
+/// Table pairs are only widened to u64 when they are used.
+pub trait SquarePair: Copy {
+    fn widen(self) -> (u64, u64);
+}
+
+impl SquarePair for (u64, u64) {
+    fn widen(self) -> (u64, u64) {
+        self
+    }
+}
+
+impl SquarePair for [u32; 2] {
+    fn widen(self) -> (u64, u64) {
+        (self[0] as u64, self[1] as u64)
+    }
+}
+
+/// Removes the pair (0, y) from `pairs` if the policy excludes 0.
+/// Pairs are sorted by increasing x (see `find_sum_of_squares_pairs`), so it can only be the first one.
+pub fn apply_zero_policy<P: SquarePair>(pairs: &[P], policy: ZeroPolicy) -> &[P] {
+    match pairs.first().map(|&pair| pair.widen()) {
+        Some((0, _)) if !policy.allows(0) => &pairs[1..],
+        _ => pairs,
+    }
+}
+
+/// Return all pairs (x, y) such that x² + y² == n using a two-pointers approach
+/// (or `find_sum_of_squares_pairs_factored` above `FACTORIZATION_THRESHOLD`).
+/// Pairs are sorted by increasing x, with x <= y, and include (0, y) when n is a perfect square.
 /// If n is not expressible, an empty vector is returned.
 pub fn find_sum_of_squares_pairs(n: u64) -> Vec<(u64, u64)> {
+    if n > FACTORIZATION_THRESHOLD {
+        return find_sum_of_squares_pairs_factored(n as u128);
+    }
     if !can_be_sum_of_two_squares(n) {
         return Vec::new();
     }
@@ -73,6 +129,94 @@ pub fn find_sum_of_squares_pairs(n: u64) -> Vec<(u64, u64)> {
     pairs
 }
 
+/// Same as `find_sum_of_squares_pairs`, for numbers that may not fit in a u64.
+/// The values x and y are at most √n, so they always fit in a u64.
+pub fn find_sum_of_squares_pairs_u128(n: u128) -> Vec<(u64, u64)> {
+    match u64::try_from(n) {
+        Ok(n) => find_sum_of_squares_pairs(n),
+        Err(_) => find_sum_of_squares_pairs_factored(n),
+    }
+}
+
+/// Product of two Gaussian integers (a + bi)(c + di).
+fn gaussian_mul((a, b): (i128, i128), (c, d): (i128, i128)) -> (i128, i128) {
+    (a * c - b * d, a * d + b * c)
+}
+
+/// Returns (x, y) with x² + y² = p, for a prime p ≡ 1 (mod 4) (Hermite–Serret / Cornacchia).
+/// A square root t of -1 mod p is found from a quadratic non-residue, then the Euclidean algorithm on (p, t)
+/// is run until the remainder drops below √p.
+pub(crate) fn prime_as_sum_of_two_squares(p: u128) -> (u128, u128) {
+    debug_assert!(p % 4 == 1);
+    let t = (2..)
+        .map(|c| factorization::pow_mod(c, (p - 1) / 4, p))
+        .find(|&t| factorization::pow_mod(t, 2, p) == p - 1)
+        .unwrap();
+
+    let sqrt_p = num_integer::sqrt(p);
+    let (mut a, mut b) = (p, t);
+    while b > sqrt_p {
+        (a, b) = (b, a % b);
+    }
+    let x = b;
+    (x, num_integer::sqrt(p - x * x))
+}
+
+/// Same as `find_sum_of_squares_pairs`, by factoring n instead of scanning all x up to √(n/2).
+/// Every representation x² + y² = n is the norm of a Gaussian integer x + yi, which is (up to units) a product of
+/// (1 + i)^a, of q^(f/2) for each prime q ≡ 3 (mod 4), and of π^j·π̄^(e-j) for each prime p = ππ̄ ≡ 1 (mod 4).
+pub fn find_sum_of_squares_pairs_factored(n: u128) -> Vec<(u64, u64)> {
+    if n == 0 {
+        return vec![(0, 0)];
+    }
+
+    // Values are bounded by √n, so every intermediate product fits in an i128.
+    let mut representations: Vec<(i128, i128)> = vec![(1, 0)];
+    let mut scale: u128 = 1;
+    for (p, e) in factorization::factorize(n) {
+        if p == 2 {
+            for z in representations.iter_mut() {
+                for _ in 0..e {
+                    *z = gaussian_mul(*z, (1, 1));
+                }
+            }
+        } else if p % 4 == 3 {
+            if e % 2 == 1 {
+                return Vec::new();
+            }
+            scale *= p.pow(e / 2);
+        } else {
+            let (x, y) = prime_as_sum_of_two_squares(p);
+            let pi = (x as i128, y as i128);
+            let mut pi_powers = vec![(1, 0)];
+            for j in 0..e as usize {
+                pi_powers.push(gaussian_mul(pi_powers[j], pi));
+            }
+            representations = representations
+                .iter()
+                .flat_map(|&z| {
+                    let pi_powers = &pi_powers;
+                    (0..=e as usize).map(move |j| {
+                        let (re, im) = pi_powers[e as usize - j];
+                        gaussian_mul(gaussian_mul(z, pi_powers[j]), (re, -im))
+                    })
+                })
+                .collect();
+        }
+    }
+
+    let mut pairs: Vec<(u64, u64)> = representations
+        .into_iter()
+        .map(|(re, im)| {
+            let (x, y) = (re.unsigned_abs() * scale, im.unsigned_abs() * scale);
+            (x.min(y) as u64, x.max(y) as u64)
+        })
+        .collect();
+    pairs.sort_unstable();
+    pairs.dedup();
+    pairs
+}
+
 /// Process a single number n and return Some((n, pairs)) if n is expressible as a sum of two squares,
 /// otherwise return None.
 fn process_n(n: u64) -> Option<(u64, Vec<(u64, u64)>)> {
@@ -95,10 +239,228 @@ fn batch_process(batch: &[u64]) -> Vec<(u64, Vec<(u64, u64)>)> {
     results
 }
 
+/// Table of all the pairs (x, y) with x <= y and x² + y² = n, for every n in a window min_n..=max_n,
+/// stored in compressed sparse rows: the pairs of n are `pairs[offsets[n - min_n]..offsets[n - min_n + 1]]`.
+/// x and y are at most √max_n, so they are stored on 32 bits.
+/// Compared to a hash map with one allocation per representable n, this takes 4 bytes per n plus 8 bytes per pair.
+/// The arrays are either computed in memory, or memory-mapped from a file written by `table_file`.
+pub struct SquareSumTable {
+    min_n: u64,
+    storage: TableStorage,
+}
+
+enum TableStorage {
+    Owned {
+        offsets: Vec<u32>,
+        pairs: Vec<[u32; 2]>,
+    },
+    /// Byte ranges of the two arrays in the mapped file (checked to be aligned and in bounds when loading).
+    Mapped {
+        map: memmap2::Mmap,
+        offsets: Range<usize>,
+        pairs: Range<usize>,
+    },
+}
+
+impl SquareSumTable {
+    /// Wraps the arrays of a memory-mapped table file.
+    /// The ranges must be in bounds and aligned for u32, and hold a valid table (see `table_file::load`).
+    pub(crate) fn from_mapped(
+        min_n: u64,
+        map: memmap2::Mmap,
+        offsets: Range<usize>,
+        pairs: Range<usize>,
+    ) -> SquareSumTable {
+        assert!(offsets.end <= map.len() && pairs.end <= map.len());
+        assert!(offsets.start % 4 == 0 && pairs.start % 4 == 0);
+        SquareSumTable {
+            min_n,
+            storage: TableStorage::Mapped {
+                map,
+                offsets,
+                pairs,
+            },
+        }
+    }
+
+    /// Offsets of the pairs of each n, from min_n to max_n + 1.
+    pub(crate) fn offsets(&self) -> &[u32] {
+        match &self.storage {
+            TableStorage::Owned { offsets, .. } => offsets,
+            // SAFETY: the range is in bounds and aligned (see `from_mapped`), and the map is never modified.
+            TableStorage::Mapped { map, offsets, .. } => unsafe {
+                std::slice::from_raw_parts(
+                    map.as_ptr().add(offsets.start) as *const u32,
+                    offsets.len() / 4,
+                )
+            },
+        }
+    }
+
+    /// All the pairs, sorted by n then by x.
+    pub(crate) fn pairs(&self) -> &[[u32; 2]] {
+        match &self.storage {
+            TableStorage::Owned { pairs, .. } => pairs,
+            // SAFETY: same as `offsets`.
+            TableStorage::Mapped { map, pairs, .. } => unsafe {
+                std::slice::from_raw_parts(
+                    map.as_ptr().add(pairs.start) as *const [u32; 2],
+                    pairs.len() / 8,
+                )
+            },
+        }
+    }
+
+    /// Returns true if the table is memory-mapped from a file.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.storage, TableStorage::Mapped { .. })
+    }
+
+    /// Returns the smallest n in the table.
+    pub fn min_n(&self) -> u64 {
+        self.min_n
+    }
+
+    /// Returns the largest n in the table.
+    pub fn max_n(&self) -> u64 {
+        self.min_n + self.offsets().len() as u64 - 2
+    }
+
+    /// Returns the pairs [x, y] such that x² + y² = n, sorted by increasing x, or None if there is none
+    /// (or if n is not in the table: callers must make sure that the window covers all their lookups).
+    /// The pairs are borrowed from the table: see `SquarePair::widen` to convert them.
+    pub fn get(&self, n: u64) -> Option<&[[u32; 2]]> {
+        if n == 0 || n < self.min_n || n > self.max_n() {
+            return None;
+        }
+        let i = (n - self.min_n) as usize;
+        let offsets = self.offsets();
+        let pairs = &self.pairs()[offsets[i] as usize..offsets[i + 1] as usize];
+        if pairs.is_empty() {
+            return None;
+        }
+        Some(pairs)
+    }
+
+    /// Returns the arrays of the table, copying them in memory first if the table is memory-mapped.
+    fn owned_arrays(&mut self) -> (&mut Vec<u32>, &mut Vec<[u32; 2]>) {
+        if self.is_mapped() {
+            self.storage = TableStorage::Owned {
+                offsets: self.offsets().to_vec(),
+                pairs: self.pairs().to_vec(),
+            };
+        }
+        match &mut self.storage {
+            TableStorage::Owned { offsets, pairs } => (offsets, pairs),
+            TableStorage::Mapped { .. } => unreachable!(),
+        }
+    }
+
+    /// Moves the window forward to min_n..=max_n: the pairs below min_n are dropped,
+    /// and only the values above the current window are computed.
+    /// Returns `OutOfRange` (and leaves the table unchanged) if the new window has too many pairs
+    /// for the 32-bit offsets.
+    pub fn slide(&mut self, min_n: u64, max_n: u64) -> Result<(), OutOfRange> {
+        assert!(min_n >= self.min_n && max_n >= self.max_n());
+        if min_n > self.max_n() {
+            *self = generate_squares_sum_window(min_n, max_n)?;
+            return Ok(());
+        }
+
+        let old_max_n = self.max_n();
+        let start = (min_n - self.min_n) as usize;
+        let extension = generate_squares_sum_window(old_max_n + 1, max_n)?;
+        let kept_pairs = self.pair_count() - self.offsets()[start] as usize;
+        let base = u32::try_from(kept_pairs).unwrap();
+        if kept_pairs + extension.pair_count() > u32::MAX as usize {
+            return Err(OutOfRange);
+        }
+
+        self.min_n = min_n;
+        let (offsets, pairs) = self.owned_arrays();
+        let dropped_pairs = offsets[start];
+        pairs.drain(..dropped_pairs as usize);
+        offsets.drain(..start);
+        for offset in offsets.iter_mut() {
+            *offset -= dropped_pairs;
+        }
+
+        offsets.pop();
+        offsets.extend(extension.offsets().iter().map(|&offset| offset + base));
+        pairs.extend_from_slice(extension.pairs());
+        Ok(())
+    }
+
+    /// Returns the number of n in the table that are a sum of two squares.
+    pub fn representable_count(&self) -> usize {
+        self.offsets().windows(2).filter(|w| w[0] < w[1]).count()
+    }
+
+    /// Returns the total number of pairs in the table.
+    pub fn pair_count(&self) -> usize {
+        self.pairs().len()
+    }
+
+    /// Returns the memory used by the table (or mapped from its file), in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.offsets().len() * std::mem::size_of::<u32>()
+            + self.pairs().len() * std::mem::size_of::<[u32; 2]>()
+    }
+}
+
 /// For each n in 1..=N, determine if n is expressible as the sum of two squares.
-/// Returns a HashMap where the key is n and the value is the vector of (x, y) pairs.
-/// Numbers that are not expressible are not included.
-pub fn generate_squares_sum_fast(n: u64) -> FxHashMap<u64, Box<[(u64, u64)]>> {
+/// Returns a table with the (x, y) pairs of every n (see `SquareSumTable`), or `OutOfRange` if there are
+/// too many pairs for its 32-bit offsets (above N ≈ 10^10).
+pub fn generate_squares_sum_fast(n: u64) -> Result<SquareSumTable, OutOfRange> {
+    generate_squares_sum_window(1, n)
+}
+
+/// Same as `generate_squares_sum_fast`, for the values min_n..=max_n only.
+///
+/// Instead of decomposing every n, this enumerates all pairs x <= y with min_n <= x² + y² <= max_n
+/// and appends each one to the bucket of x² + y²: a first pass counts the pairs of each n, a second pass writes
+/// them at their offset in a single buffer. x is the outer loop, so each bucket is sorted by increasing x.
+pub fn generate_squares_sum_window(min_n: u64, max_n: u64) -> Result<SquareSumTable, OutOfRange> {
+    let for_each_pair = |f: &mut dyn FnMut(u64, u32, u32)| {
+        let mut x = 0;
+        while 2 * x * x <= max_n {
+            // Smallest y such that x² + y² >= min_n.
+            let y_min = if min_n > x * x {
+                num_integer::sqrt(min_n - x * x - 1) + 1
+            } else {
+                0
+            };
+            let mut y = y_min.max(x).max(1);
+            while x * x + y * y <= max_n {
+                f(x * x + y * y - min_n, x as u32, y as u32);
+                y += 1;
+            }
+            x += 1;
+        }
+    };
+
+    let mut offsets = vec![0u32; (max_n + 2 - min_n) as usize];
+    for_each_pair(&mut |i, _, _| offsets[i as usize + 1] += 1);
+    for i in 1..offsets.len() {
+        offsets[i] = offsets[i].checked_add(offsets[i - 1]).ok_or(OutOfRange)?;
+    }
+
+    let mut pairs = vec![[0, 0]; *offsets.last().unwrap() as usize];
+    let mut cursors = offsets.clone();
+    for_each_pair(&mut |i, x, y| {
+        pairs[cursors[i as usize] as usize] = [x, y];
+        cursors[i as usize] += 1;
+    });
+
+    Ok(SquareSumTable {
+        min_n,
+        storage: TableStorage::Owned { offsets, pairs },
+    })
+}
+
+/// Same as `generate_squares_sum_fast`, by decomposing each n with `find_sum_of_squares_pairs` (in parallel).
+/// Kept as a reference implementation.
+pub fn generate_squares_sum_by_decomposition(n: u64) -> FxHashMap<u64, Box<[(u64, u64)]>> {
     let cpus = num_cpus::get() as u64;
     // Compute batch size: ceil(n / cpus) // 8, but at least 1.
     let batch_size = (((n + cpus - 1) / cpus) / 8).max(1) as usize;
@@ -112,48 +474,276 @@ pub fn generate_squares_sum_fast(n: u64) -> FxHashMap<u64, Box<[(u64, u64)]>> {
 }
 
 /// Returns (true, r) if n is a perfect square (with r = √n), else (false, 0).
+/// Most non-squares are rejected by `is_square_residue`, without computing a square root.
 pub fn is_perfect_square(n: u64) -> (bool, u64) {
-    if n == 0 {
-        return (true, 0);
+    match exact_square_root(n, n) {
+        Some(r) => (true, r),
+        None => (false, 0),
+    }
+}
+
+/// Same as `is_perfect_square`, for numbers that may not fit in a u64.
+pub fn is_perfect_square_u128(n: u128) -> (bool, u64) {
+    if let Ok(n) = u64::try_from(n) {
+        return is_perfect_square(n);
+    }
+    // All the moduli divide SQUARE_RESIDUE_PERIOD, so a single u128 division is needed.
+    match exact_square_root(n, (n % SQUARE_RESIDUE_PERIOD as u128) as u64) {
+        // The root of a u128 is below 2^64.
+        Some(r) => (true, r as u64),
+        None => (false, 0),
     }
+}
 
-    let r = num_integer::sqrt(n);
-    (r * r == n, r)
+/// Returns √n if n is a perfect square, given n modulo `SQUARE_RESIDUE_PERIOD` (or n itself) as `residue`.
+fn exact_square_root<T: num_integer::Roots + Copy>(n: T, residue: u64) -> Option<T> {
+    if !is_square_residue(residue) {
+        return None;
+    }
+    let r = n.sqrt();
+    (r * r == n).then_some(r)
 }
 
-pub struct PrecomputedPerfectSquares {
-    pub map: FxHashMap<u64, (bool, u64)>,
-    pub max: u64,
+/// Returns false if n is not a quadratic residue modulo one of `POWER_RESIDUE_MODULI` (so not a perfect square).
+/// Only 12/64, 16/63, 21/65 and 6/11 of the residues are squares: about 99% of the non-squares are rejected.
+pub(crate) fn is_square_residue(n: u64) -> bool {
+    SQUARE_RESIDUE_MASKS
+        .iter()
+        .all(|&(m, mask)| mask & (1 << (n % m)) != 0)
 }
 
-impl PrecomputedPerfectSquares {
-    pub fn get(&self, n: u64) -> Option<(bool, u64)> {
-        if n > self.max {
-            let res = is_perfect_square(n);
-            return Some(res);
+/// Returns ⌊n^(1/k)⌋.
+pub fn integer_root(n: u64, k: u32) -> u64 {
+    num_integer::Roots::nth_root(&n, k)
+}
+
+/// Moduli used to filter out numbers that are not k-th powers before computing an exact root.
+const POWER_RESIDUE_MODULI: [u64; 4] = [64, 63, 65, 11];
+
+/// Product of `POWER_RESIDUE_MODULI`.
+pub(crate) const SQUARE_RESIDUE_PERIOD: u64 = 64 * 63 * 65 * 11;
+
+/// Residue masks of `POWER_RESIDUE_MODULI` for squares, computed at compile time.
+const SQUARE_RESIDUE_MASKS: [(u64, u128); 4] = [
+    (64, power_residue_mask(64, 2)),
+    (63, power_residue_mask(63, 2)),
+    (65, power_residue_mask(65, 2)),
+    (11, power_residue_mask(11, 2)),
+];
+
+/// Returns the mask whose bit r is set if r is a k-th power residue modulo m (m <= 128).
+const fn power_residue_mask(m: u64, exponent: u32) -> u128 {
+    let mut mask = 0u128;
+    let mut x = 0;
+    while x < m {
+        let mut residue = 1;
+        let mut i = 0;
+        while i < exponent {
+            residue = residue * x % m;
+            i += 1;
         }
-        self.map.get(&n).copied()
+        mask |= 1 << residue;
+        x += 1;
     }
+    mask
 }
 
-pub fn precompute_perfect_squares(max_val: u64) -> PrecomputedPerfectSquares {
-    // Using multiprocessing
-    let cpus = num_cpus::get() as u64;
-    // Compute batch size: ceil(n / cpus) // 8, but at least 1.
-    let batch_size = (((max_val + cpus - 1) / cpus) / 8).max(1) as usize;
-
-    PrecomputedPerfectSquares {
-        max: max_val,
-        map: (0..=max_val)
-            .collect::<Vec<u64>>() // Collecting the range into a Vec
-            .par_chunks(batch_size)
-            .flat_map_iter(|batch| {
-                batch
-                    .iter()
-                    .map(|&i| (i, is_perfect_square(i)))
-                    .filter(|(_, (is_ps, _))| *is_ps)
-                    .collect::<Vec<_>>()
-            })
-            .collect(),
+/// Test for k-th powers (squares, cubes, fourth powers...).
+/// Numbers that are not k-th power residues modulo small moduli are rejected without computing a root.
+pub struct PowerTest {
+    pub exponent: u32,
+    /// For each modulus m, bit r of the mask is set if r is a k-th power residue modulo m.
+    residue_masks: [(u64, u128); 4],
+}
+
+impl PowerTest {
+    pub fn new(exponent: u32) -> PowerTest {
+        PowerTest {
+            exponent,
+            residue_masks: POWER_RESIDUE_MODULI.map(|m| (m, power_residue_mask(m, exponent))),
+        }
+    }
+
+    /// Returns (true, r) if n = r^k (with k the exponent), else (false, _).
+    pub fn check(&self, n: u64) -> (bool, u64) {
+        if self
+            .residue_masks
+            .iter()
+            .any(|&(m, mask)| mask & (1 << (n % m)) == 0)
+        {
+            return (false, 0);
+        }
+        let r = integer_root(n, self.exponent);
+        (r.checked_pow(self.exponent) == Some(n), r)
+    }
+}
+
+/// Return all pairs (x, y) with x <= y such that x^k + y^k == n, using a two-pointers approach.
+/// For k = 2, this is `find_sum_of_squares_pairs`.
+pub fn find_sum_of_powers_pairs(n: u64, k: u32) -> Vec<(u64, u64)> {
+    if k == 2 {
+        return find_sum_of_squares_pairs(n);
+    }
+
+    let mut pairs = Vec::new();
+    let mut x = 0;
+    let mut y = integer_root(n, k);
+
+    // x and y are at most the k-th root of n, so only their sum can overflow (and it is then bigger than n).
+    while x <= y {
+        let sum_powers = x.pow(k).checked_add(y.pow(k));
+        if sum_powers == Some(n) {
+            pairs.push((x, y));
+            x += 1;
+        } else if sum_powers.is_some_and(|sum| sum < n) {
+            x += 1;
+        } else if y > 0 {
+            y -= 1;
+        } else {
+            break;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_perfect_square() {
+        for n in 0..200_000u64 {
+            let r = num_integer::sqrt(n);
+            assert_eq!(is_perfect_square(n).0, r * r == n, "n = {}", n);
+        }
+        let r = 12_345_678_901_234_567u128;
+        assert_eq!(is_perfect_square_u128(r * r), (true, r as u64));
+        assert!(!is_perfect_square_u128(r * r + 1).0);
+        assert!(!is_perfect_square_u128(r * r - 1).0);
+    }
+
+    /// The lookup table that `is_perfect_square` replaced: every square up to `max`, with its root.
+    fn reference_square_map(max: u64) -> FxHashMap<u64, u64> {
+        (0..=num_integer::sqrt(max)).map(|r| (r * r, r)).collect()
+    }
+
+    #[test]
+    fn test_is_perfect_square_matches_square_map() {
+        let max = 3_000_000;
+        let map = reference_square_map(max);
+        let lookup = |n: u64| map.get(&n).map_or((false, 0), |&r| (true, r));
+        for n in 0..=max {
+            assert_eq!(is_perfect_square(n), lookup(n), "n = {}", n);
+        }
+        // Around large squares, where the residue filter lets most values through to the exact root.
+        for r in (1u64..u32::MAX as u64).step_by(65_537) {
+            assert_eq!(is_perfect_square(r * r), (true, r));
+            assert_eq!(is_perfect_square(r * r - 1).0, r == 1);
+            assert!(!is_perfect_square(r * r + 1).0);
+        }
+    }
+
+    #[test]
+    fn test_power_test() {
+        for k in 2..=4 {
+            let power_test = PowerTest::new(k);
+            for n in 0..10_000u64 {
+                let r = integer_root(n, k);
+                assert_eq!(power_test.check(n).0, r.pow(k) == n, "n = {}, k = {}", n, k);
+            }
+        }
+        assert_eq!(PowerTest::new(3).check(1728), (true, 12));
+        assert_eq!(
+            PowerTest::new(4).check(3u64.pow(4) * 1000u64.pow(4)),
+            (true, 3000)
+        );
+    }
+
+    #[test]
+    fn test_generate_squares_sum_fast() {
+        for n in [0, 1, 2, 25, 1000, 100_000] {
+            let table = generate_squares_sum_fast(n).unwrap();
+            let reference = generate_squares_sum_by_decomposition(n);
+            assert_eq!(table.max_n(), n);
+            assert_eq!(table.representable_count(), reference.len());
+            for m in 0..=n + 1 {
+                let pairs = table
+                    .get(m)
+                    .map(|pairs| pairs.iter().map(|&pair| pair.widen()).collect::<Vec<_>>());
+                assert_eq!(
+                    pairs.as_deref(),
+                    reference.get(&m).map(|p| &p[..]),
+                    "m = {}",
+                    m
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_square_sum_table_window() {
+        let full = generate_squares_sum_fast(50_000).unwrap();
+        let collect = |table: &SquareSumTable, n: u64| table.get(n).map(<[_]>::to_vec);
+
+        let mut window = generate_squares_sum_window(10_000, 20_000).unwrap();
+        for (min_n, max_n) in [
+            (10_000, 20_000),
+            (15_000, 30_000),
+            (29_000, 30_000),
+            (40_000, 50_000),
+        ] {
+            window.slide(min_n, max_n).unwrap();
+            assert_eq!((window.min_n(), window.max_n()), (min_n, max_n));
+            assert_eq!(collect(&window, min_n - 1), None);
+            for n in min_n..=max_n {
+                assert_eq!(collect(&window, n), collect(&full, n), "n = {}", n);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_sum_of_squares_pairs_factored() {
+        for n in 0..20_000u64 {
+            assert_eq!(
+                find_sum_of_squares_pairs_factored(n as u128),
+                find_sum_of_squares_pairs(n),
+                "n = {}",
+                n
+            );
+        }
+        // 5^3 * 13^2 * 17 * 3^2 * 2^6, just above FACTORIZATION_THRESHOLD.
+        let n = 5u64.pow(3) * 13 * 13 * 17 * 9 * 64;
+        assert_eq!(find_sum_of_squares_pairs(n).len(), 12);
+
+        // A 38-digit N = (k*3*p²)², far too large for a linear scan.
+        let root = 3 * 7u128.pow(2) * 43 * 998_244_353 * 1_000_003;
+        let n = root * root;
+        let pairs = find_sum_of_squares_pairs_u128(n);
+        assert!(pairs.contains(&(0, root as u64)));
+        for &(x, y) in &pairs {
+            assert_eq!((x as u128).pow(2) + (y as u128).pow(2), n);
+        }
+        // 3, 7, 43 and 1_000_003 are ≡ 3 (mod 4): only 998_244_353² gives more than one pair.
+        assert_eq!(pairs.len(), 2);
+    }
+
+    #[test]
+    fn test_find_sum_of_powers_pairs() {
+        // 1729 is the smallest number that is a sum of two cubes in two different ways.
+        assert_eq!(find_sum_of_powers_pairs(1729, 3), vec![(1, 12), (9, 10)]);
+        // 635318657 is the smallest number that is a sum of two fourth powers in two different ways.
+        assert_eq!(
+            find_sum_of_powers_pairs(635318657, 4),
+            vec![(59, 158), (133, 134)]
+        );
+        assert_eq!(
+            find_sum_of_powers_pairs(25, 2),
+            find_sum_of_squares_pairs(25)
+        );
+        // Near u64::MAX, the sums of the two-pointer scan overflow.
+        let largest_cube = 2642245u64.pow(3);
+        assert!(find_sum_of_powers_pairs(largest_cube + 1, 3).contains(&(1, 2642245)));
+        assert!(PowerTest::new(3).check(largest_cube).0);
+        assert!(!PowerTest::new(3).check(u64::MAX).0);
     }
 }
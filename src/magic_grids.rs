@@ -0,0 +1,549 @@
+use fxhash::FxHashSet;
+use std::fmt;
+
+use crate::generate_squares;
+use crate::generate_squares::{PowerTest, SquarePair, SquareSumTable};
+use crate::utils;
+use crate::utils::OutOfRange;
+
+/// A n×n square of k-th powers (squares, cubes...), with its cells stored in row-major order.
+/// Unlike `magic_squares::Solution`, the size is not fixed to 3x3 and the exponent is not fixed to 2.
+#[derive(Clone, Debug)]
+pub struct GridSolution {
+    pub size: usize,
+    pub exponent: u32,
+    pub N: u64,
+    pub cells: Vec<u64>,
+}
+
+impl GridSolution {
+    pub fn get(&self, row: usize, col: usize) -> u64 {
+        self.cells[row * self.size + col]
+    }
+
+    /// Returns the sum of the k-th powers of every line of the grid: rows, then columns, then the main diagonal
+    /// and the anti-diagonal.
+    /// Fails with `OutOfRange` if a power or a sum doesn't fit in a u64.
+    pub fn line_sums(&self) -> Result<Vec<u64>, OutOfRange> {
+        let n = self.size;
+        let rows = (0..n).map(|r| (0..n).map(|c| (r, c)).collect());
+        let cols = (0..n).map(|c| (0..n).map(|r| (r, c)).collect());
+        let main_diagonal = (0..n).map(|k| (k, k)).collect();
+        let anti_diagonal = (0..n).map(|k| (k, n - 1 - k)).collect();
+
+        rows.chain(cols)
+            .chain([main_diagonal, anti_diagonal])
+            .map(|line: Vec<(usize, usize)>| {
+                line.into_iter().try_fold(0u64, |sum, (row, col)| {
+                    let power = self.get(row, col).checked_pow(self.exponent);
+                    power
+                        .and_then(|power| sum.checked_add(power))
+                        .ok_or(OutOfRange)
+                })
+            })
+            .collect()
+    }
+
+    /// Returns true if all rows, columns and both diagonals sum to N, with distinct values.
+    pub fn is_magic(&self) -> bool {
+        let unique: FxHashSet<_> = self.cells.iter().collect();
+        // A line whose sum overflows can't sum to N.
+        unique.len() == self.cells.len()
+            && self
+                .line_sums()
+                .is_ok_and(|sums| sums.iter().all(|&s| s == self.N))
+    }
+
+    /// Returns the canonical representative of the grid under rotations and reflections:
+    /// the lexicographically smallest cells among its 8 symmetric variants.
+    pub fn canonical_cells(&self) -> Vec<u64> {
+        let n = self.size;
+        // Each symmetry maps the cell (row, col) of the new grid to a cell of the original grid.
+        let symmetries: [fn(usize, usize, usize) -> (usize, usize); 8] = [
+            |_, r, c| (r, c),
+            |n, r, c| (n - 1 - c, r),
+            |n, r, c| (n - 1 - r, n - 1 - c),
+            |n, r, c| (c, n - 1 - r),
+            |n, r, c| (r, n - 1 - c),
+            |n, r, c| (n - 1 - r, c),
+            |_, r, c| (c, r),
+            |n, r, c| (n - 1 - c, n - 1 - r),
+        ];
+        symmetries
+            .iter()
+            .map(|sym| {
+                (0..n * n)
+                    .map(|idx| {
+                        let (r, c) = sym(n, idx / n, idx % n);
+                        self.get(r, c)
+                    })
+                    .collect::<Vec<u64>>()
+            })
+            .min()
+            .unwrap()
+    }
+}
+
+/// Two grids are equal if they are the same grid up to rotations and reflections.
+impl PartialEq for GridSolution {
+    fn eq(&self, other: &Self) -> bool {
+        self.size == other.size
+            && self.exponent == other.exponent
+            && self.N == other.N
+            && self.canonical_cells() == other.canonical_cells()
+    }
+}
+
+impl Eq for GridSolution {}
+
+impl std::hash::Hash for GridSolution {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.size.hash(state);
+        self.exponent.hash(state);
+        self.N.hash(state);
+        self.canonical_cells().hash(state);
+    }
+}
+
+impl fmt::Display for GridSolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exponent = match self.exponent {
+            2 => "²".to_string(),
+            3 => "³".to_string(),
+            4 => "⁴".to_string(),
+            k => format!("^{}", k),
+        };
+        for row in self.cells.chunks(self.size) {
+            let row: Vec<String> = row.iter().map(|x| format!("{}{}", x, exponent)).collect();
+            writeln!(f, "{}", row.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns all pairs (x, y) with 0 < x < y such that x^k + y^k = X.
+/// The precomputed table is only used for squares (k = 2).
+fn positive_distinct_pairs(
+    precomputed_square_sums: Option<&SquareSumTable>,
+    X: u64,
+    exponent: u32,
+) -> Vec<(u64, u64)> {
+    let pairs = match precomputed_square_sums {
+        Some(prec) if exponent == 2 => match prec.get(X) {
+            Some(pairs) => pairs.iter().map(|&pair| pair.widen()).collect(),
+            None => return vec![],
+        },
+        _ => generate_squares::find_sum_of_powers_pairs(X, exponent),
+    };
+    pairs.into_iter().filter(|&(x, y)| 0 < x && x < y).collect()
+}
+
+/// Returns all strictly increasing `count`-tuples of positive integers whose k-th powers sum to N.
+/// The last two values of each tuple come from the sum-of-two-powers decomposition.
+fn sorted_decompositions(
+    precomputed_square_sums: Option<&SquareSumTable>,
+    N: u64,
+    count: usize,
+    exponent: u32,
+) -> Vec<Vec<u64>> {
+    fn recurse(
+        precomputed_square_sums: Option<&SquareSumTable>,
+        remaining: u64,
+        count: usize,
+        exponent: u32,
+        prefix: &mut Vec<u64>,
+        results: &mut Vec<Vec<u64>>,
+    ) {
+        let min = prefix.last().copied().unwrap_or(0);
+        if count == 2 {
+            for (x, y) in positive_distinct_pairs(precomputed_square_sums, remaining, exponent) {
+                if x > min {
+                    let mut tuple = prefix.clone();
+                    tuple.extend([x, y]);
+                    results.push(tuple);
+                }
+            }
+            return;
+        }
+
+        // All following values are bigger than v, so count * v^k < remaining.
+        // A power or a product that overflows is above remaining, which ends the loop.
+        let mut v = min + 1;
+        while let Some(power) = v.checked_pow(exponent).filter(|&power| {
+            (count as u64)
+                .checked_mul(power)
+                .is_some_and(|sum| sum < remaining)
+        }) {
+            prefix.push(v);
+            recurse(
+                precomputed_square_sums,
+                remaining - power,
+                count - 1,
+                exponent,
+                prefix,
+                results,
+            );
+            prefix.pop();
+            v += 1;
+        }
+    }
+
+    let mut results = vec![];
+    recurse(
+        precomputed_square_sums,
+        N,
+        count,
+        exponent,
+        &mut vec![],
+        &mut results,
+    );
+    results
+}
+
+/// Every sum computed by the grid searches is at most size·N + 2 (the powers of a line, each at most N, plus the room
+/// left for two more powers): returns `OutOfRange` if that bound doesn't fit in a u64.
+pub fn check_grid_range(size: usize, N: u64) -> Result<(), OutOfRange> {
+    (size as u64)
+        .checked_mul(N)
+        .and_then(|bound| bound.checked_add(2))
+        .map(|_| ())
+        .ok_or(OutOfRange)
+}
+
+/// Finds all n×n semi-magic squares of k-th powers (rows and columns sum to N, diagonals are free) with distinct
+/// positive values.
+///
+/// Semi-magic squares are invariant under any permutation of rows and columns, so we only return one representative
+/// per class (and its transpose): the top-left cell is the smallest value, and the first row and the first column
+/// are sorted in increasing order.
+///
+/// The first n-2 rows are picked among the decompositions of N into n k-th powers. The last two rows are then
+/// constrained column by column: for each column, the two remaining cells form a pair (x, y) with
+/// x^k + y^k = N - (sum of the column so far).
+/// Fails with `OutOfRange` if N is too large for the grid (see `check_grid_range`).
+pub fn find_semi_magic_grids(
+    precomputed_square_sums: Option<&SquareSumTable>,
+    size: usize,
+    exponent: u32,
+    N: u64,
+) -> Result<Vec<GridSolution>, OutOfRange> {
+    assert!(size >= 3, "grids must be at least 3x3");
+    assert!(exponent >= 2, "the exponent must be at least 2");
+    check_grid_range(size, N)?;
+
+    let decompositions = sorted_decompositions(precomputed_square_sums, N, size, exponent);
+    let row_permutations: Vec<Vec<usize>> = utils::permutations(&(0..size).collect::<Vec<_>>());
+
+    let mut search = SemiMagicSearch {
+        precomputed_square_sums,
+        size,
+        exponent,
+        power_test: PowerTest::new(exponent),
+        N,
+        decompositions: &decompositions,
+        row_permutations: &row_permutations,
+        rows: vec![],
+        used: FxHashSet::default(),
+        column_sums: vec![0; size],
+        results: vec![],
+    };
+    search.fill_rows();
+    Ok(search.results)
+}
+
+/// State of the backtracking search of `find_semi_magic_grids`.
+struct SemiMagicSearch<'a> {
+    precomputed_square_sums: Option<&'a SquareSumTable>,
+    size: usize,
+    exponent: u32,
+    power_test: PowerTest,
+    N: u64,
+    decompositions: &'a [Vec<u64>],
+    row_permutations: &'a [Vec<usize>],
+    rows: Vec<Vec<u64>>,
+    used: FxHashSet<u64>,
+    column_sums: Vec<u64>,
+    results: Vec<GridSolution>,
+}
+
+impl SemiMagicSearch<'_> {
+    /// Picks the first n-2 rows, then completes the grid.
+    fn fill_rows(&mut self) {
+        if self.rows.len() == self.size - 2 {
+            self.fill_last_two_rows();
+            return;
+        }
+
+        let decompositions = self.decompositions;
+        for decomposition in decompositions {
+            if decomposition.iter().any(|x| self.used.contains(x)) {
+                continue;
+            }
+            // The top-left cell is the smallest value of the grid (decompositions are sorted).
+            if self
+                .rows
+                .first()
+                .is_some_and(|first| decomposition[0] <= first[0])
+            {
+                continue;
+            }
+
+            // The first row is sorted, the next rows can be in any order (as long as the first column stays sorted).
+            let candidates: Vec<Vec<u64>> = if self.rows.is_empty() {
+                vec![decomposition.clone()]
+            } else {
+                self.row_permutations
+                    .iter()
+                    .map(|perm| perm.iter().map(|&idx| decomposition[idx]).collect())
+                    .collect()
+            };
+
+            for row in candidates {
+                if let Some(previous) = self.rows.last() {
+                    if row[0] <= previous[0] {
+                        continue;
+                    }
+                }
+                // Each column needs room for (at least) two more positive k-th powers.
+                if (0..self.size)
+                    .any(|col| self.column_sums[col] + row[col].pow(self.exponent) + 2 > self.N)
+                {
+                    continue;
+                }
+
+                self.push_row(row);
+                self.fill_rows();
+                self.pop_row();
+            }
+        }
+    }
+
+    fn push_row(&mut self, row: Vec<u64>) {
+        for (col, &x) in row.iter().enumerate() {
+            self.column_sums[col] += x.pow(self.exponent);
+            self.used.insert(x);
+        }
+        self.rows.push(row);
+    }
+
+    fn pop_row(&mut self) {
+        let row = self.rows.pop().unwrap();
+        for (col, &x) in row.iter().enumerate() {
+            self.column_sums[col] -= x.pow(self.exponent);
+            self.used.remove(&x);
+        }
+    }
+
+    /// Completes the grid column by column: the two remaining cells of each column must be a pair (x, y)
+    /// with x^k + y^k = N - (sum of the column so far).
+    fn fill_last_two_rows(&mut self) {
+        let mut column_pairs = vec![];
+        for col in 0..self.size {
+            let pairs: Vec<(u64, u64)> = positive_distinct_pairs(
+                self.precomputed_square_sums,
+                self.N - self.column_sums[col],
+                self.exponent,
+            )
+            .into_iter()
+            .filter(|(x, y)| !self.used.contains(x) && !self.used.contains(y))
+            .filter(|(x, _)| self.rows.first().is_none_or(|first| *x > first[0]))
+            .collect();
+            if pairs.is_empty() {
+                return;
+            }
+            column_pairs.push(pairs);
+        }
+
+        let mut before_last = vec![];
+        let mut last = vec![];
+        self.choose_column_pairs(&column_pairs, &mut before_last, &mut last);
+    }
+
+    /// The last cell of the before-last row is determined by its row sum, and the last cell of the last row by
+    /// its column sum: both must be k-th powers. The last row is then correct, as all columns are correct.
+    fn complete_last_column(&mut self, before_last: &[u64], last: &[u64]) {
+        let col = self.size - 1;
+        let k = self.exponent;
+
+        let row_sum: u64 = before_last.iter().map(|x| x.pow(k)).sum();
+        if row_sum >= self.N {
+            return;
+        }
+        let (is_power, top) = self.power_test.check(self.N - row_sum);
+        if !is_power {
+            return;
+        }
+
+        let column_rest = self.N - self.column_sums[col];
+        if top.pow(k) >= column_rest {
+            return;
+        }
+        let (is_power, bottom) = self.power_test.check(column_rest - top.pow(k));
+        if !is_power {
+            return;
+        }
+
+        let min = self.rows[0][0];
+        if top <= min || bottom <= min || top == bottom {
+            return;
+        }
+        if [top, bottom]
+            .iter()
+            .any(|x| self.used.contains(x) || before_last.contains(x) || last.contains(x))
+        {
+            return;
+        }
+
+        let mut cells: Vec<u64> = self.rows.concat();
+        cells.extend(before_last.iter().chain([&top]));
+        cells.extend(last.iter().chain([&bottom]));
+        self.results.push(GridSolution {
+            size: self.size,
+            exponent: self.exponent,
+            N: self.N,
+            cells,
+        });
+    }
+
+    fn choose_column_pairs(
+        &mut self,
+        column_pairs: &[Vec<(u64, u64)>],
+        before_last: &mut Vec<u64>,
+        last: &mut Vec<u64>,
+    ) {
+        let col = before_last.len();
+        if col == self.size - 1 {
+            self.complete_last_column(before_last, last);
+            return;
+        }
+
+        for &(x, y) in &column_pairs[col] {
+            // In the first column, the values must stay sorted.
+            let orientations: &[(u64, u64)] = if col == 0 {
+                &[(x, y)]
+            } else {
+                &[(x, y), (y, x)]
+            };
+            for &(top, bottom) in orientations {
+                if col == 0 && self.rows.last().is_some_and(|row| top <= row[0]) {
+                    continue;
+                }
+                if before_last.contains(&top)
+                    || before_last.contains(&bottom)
+                    || last.contains(&top)
+                    || last.contains(&bottom)
+                {
+                    continue;
+                }
+                before_last.push(top);
+                last.push(bottom);
+                self.choose_column_pairs(column_pairs, before_last, last);
+                before_last.pop();
+                last.pop();
+            }
+        }
+    }
+}
+
+/// Finds all n×n magic squares of k-th powers (rows, columns and both diagonals sum to N) with distinct positive values.
+/// Each square is returned once, up to rotations and reflections.
+///
+/// Every magic square is also semi-magic, so we start from the semi-magic squares and try every permutation of
+/// their rows and columns to make the diagonals correct.
+/// Fails with `OutOfRange` for the same N as `find_semi_magic_grids`.
+pub fn find_magic_grids(
+    precomputed_square_sums: Option<&SquareSumTable>,
+    size: usize,
+    exponent: u32,
+    N: u64,
+) -> Result<Vec<GridSolution>, OutOfRange> {
+    let permutations: Vec<Vec<usize>> = utils::permutations(&(0..size).collect::<Vec<_>>());
+
+    let mut seen: FxHashSet<GridSolution> = FxHashSet::default();
+    let mut results = vec![];
+    for semi_magic in find_semi_magic_grids(precomputed_square_sums, size, exponent, N)? {
+        for row_perm in &permutations {
+            for col_perm in &permutations {
+                let grid = GridSolution {
+                    size,
+                    exponent,
+                    N,
+                    cells: (0..size * size)
+                        .map(|idx| semi_magic.get(row_perm[idx / size], col_perm[idx % size]))
+                        .collect(),
+                };
+                if grid.is_magic() && seen.insert(grid.clone()) {
+                    results.push(grid);
+                }
+            }
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_magic_grids_4x4() {
+        // Euler's 4x4 magic square of squares.
+        let euler = GridSolution {
+            size: 4,
+            exponent: 2,
+            N: 8515,
+            cells: vec![
+                68, 29, 41, 37, 17, 31, 79, 32, 59, 28, 23, 61, 11, 77, 8, 49,
+            ],
+        };
+        assert!(euler.is_magic());
+
+        let solutions = find_magic_grids(None, 4, 2, 8515).unwrap();
+        assert!(solutions.contains(&euler));
+        assert!(solutions.iter().all(|grid| grid.is_magic()));
+    }
+
+    #[test]
+    fn test_find_semi_magic_grids_of_cubes() {
+        // A 4x4 semi-magic square of cubes, in the representative form (smallest value top-left, first row and
+        // first column sorted).
+        let cubes = GridSolution {
+            size: 4,
+            exponent: 3,
+            N: 7095816,
+            cells: vec![
+                2, 24, 144, 160, 16, 192, 18, 20, 108, 9, 150, 135, 180, 15, 90, 81,
+            ],
+        };
+        assert!(cubes.line_sums().unwrap()[..8]
+            .iter()
+            .all(|&s| s == cubes.N));
+
+        let solutions = find_semi_magic_grids(None, 4, 3, cubes.N).unwrap();
+        assert!(solutions.contains(&cubes));
+        assert!(solutions.iter().all(|grid| grid.exponent == 3
+            && grid.line_sums().unwrap()[..8].iter().all(|&s| s == grid.N)));
+    }
+
+    #[test]
+    fn test_grids_out_of_range() {
+        // The sums of a 4x4 grid would overflow.
+        assert_eq!(
+            find_semi_magic_grids(None, 4, 3, u64::MAX / 2),
+            Err(OutOfRange)
+        );
+        assert_eq!(find_magic_grids(None, 4, 2, u64::MAX - 1), Err(OutOfRange));
+
+        // 2^70 doesn't fit in a u64: the decompositions only use 1, so there is no grid.
+        assert_eq!(find_semi_magic_grids(None, 3, 70, 1000), Ok(vec![]));
+
+        let huge = GridSolution {
+            size: 3,
+            exponent: 40,
+            N: 1000,
+            cells: vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+        };
+        assert_eq!(huge.line_sums(), Err(OutOfRange));
+        assert!(!huge.is_magic());
+    }
+}
@@ -1,117 +1,201 @@
-use fxhash::{FxHashMap, FxHashSet};
-use std::sync::Arc;
-use std::time::Instant;
+use fxhash::FxHashSet;
+use rayon::prelude::*;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::ControlFlow;
 
 use crate::generate_squares;
-use crate::generate_squares::PrecomputedPerfectSquares;
+use crate::generate_squares::{SquarePair, SquareSumTable, ZeroPolicy};
 use crate::utils;
 
+/// Largest N supported by the search. Above it, the sum of the squares of an axis (at most 3N) could overflow
+/// (and its deviation from N would not fit in an i128).
+pub const MAX_N: u128 = u128::MAX >> 3;
+
+/// Options controlling which squares are accepted by the search.
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    /// Maximum number of axes whose sum can differ from N. By construction, at most 3 axes can be incorrect.
+    /// 0 only accepts perfect squares, 1 accepts quasi magic squares, 2 accepts "two-lines-off" squares...
+    pub max_incorrect_axes: usize,
+    /// Accept squares whose 9 values are not all distinct (e.g. the Parker square).
+    /// Such squares are classified as `SolutionType::RepeatedEntries`.
+    pub allow_repeated_entries: bool,
+    /// Whether squares containing 0 are searched. Such squares are tagged with `Solution::zero_entries`.
+    pub zero_policy: ZeroPolicy,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            max_incorrect_axes: 1,
+            allow_repeated_entries: false,
+            zero_policy: ZeroPolicy::StrictlyPositive,
+        }
+    }
+}
+
 /// Checks if the given 3 pairs (with extra value `e` and target N) form a (partial or perfect) magic square.
 /// In the ordering the three pairs correspond to (a,i), (b,h) and (d,f) respectively.
 fn is_magic_square(
-    perfect_squares_precomp: Option<&PrecomputedPerfectSquares>,
+    options: &SearchOptions,
     ordering: &[(u64, u64); 3],
     e: u64,
-    N: u64,
-    X: u64,
+    N: u128,
 ) -> Option<Solution> {
     let (a, i_val) = ordering[0];
     let (b, h) = ordering[1];
     let (d, f) = ordering[2];
 
     // S4: a² + b² + c² = N  =>  c² = N - a² - b².
-    let sum_ab = a * a + b * b;
-    if sum_ab > N {
+    // S2: g² + h² + i² = N  =>  g² = N - h² - i².
+    let sum_ab = sq(a) + sq(b);
+    let sum_hi = sq(h) + sq(i_val);
+    if sum_ab > N || sum_hi > N {
         return None;
     }
     let c2 = N - sum_ab;
-    let (is_ps_c, c) = match perfect_squares_precomp {
-        Some(prec) => match prec.get(c2) {
-            Some((is_ps, c)) => (is_ps, c),
-            None => return None,
-        },
-        None => generate_squares::is_perfect_square(c2),
-    };
-    if !is_ps_c {
+    let g2 = N - sum_hi;
+
+    // Cheap rejection first, before taking any square root: the three rows, the middle column and the main diagonal
+    // are correct by construction, so only the left and right columns and the anti-diagonal can be incorrect,
+    // and their sums only need c² and g².
+    let incorrect_count = [sq(a) + sq(d) + g2, c2 + sq(f) + sq(i_val), c2 + sq(e) + g2]
+        .iter()
+        .filter(|&&sum| sum != N)
+        .count();
+    if incorrect_count > options.max_incorrect_axes {
         return None;
     }
 
-    // S2: g² + h² + i² = N  =>  g² = N - h² - i².
-    let sum_hi = h * h + i_val * i_val;
-    if sum_hi > N {
+    let (is_ps_c, c) = generate_squares::is_perfect_square_u128(c2);
+    if !is_ps_c || !options.zero_policy.allows(c) {
         return None;
     }
-    let g2 = N - sum_hi;
-    let (is_ps_g, g) = match perfect_squares_precomp {
-        Some(prec) => match prec.get(g2) {
-            Some((is_ps, g)) => (is_ps, g),
-            None => return None,
-        },
-        None => generate_squares::is_perfect_square(g2),
-    };
-
-    if !is_ps_g {
+    let (is_ps_g, g) = generate_squares::is_perfect_square_u128(g2);
+    if !is_ps_g || !options.zero_policy.allows(g) {
         return None;
     }
 
-    // Check that all 9 numbers are distinct.
-    let assignment = vec![a, b, c, d, e, f, g, h, i_val];
-    let unique: FxHashSet<_> = assignment.iter().cloned().collect();
-    if unique.len() < 9 {
+    // Check that all 9 numbers are distinct (unless repeated entries are allowed).
+    let assignment = [a, b, c, d, e, f, g, h, i_val];
+    let mut sorted = assignment;
+    sorted.sort_unstable();
+    let repeated_entries = sorted.windows(2).filter(|pair| pair[0] == pair[1]).count();
+    if repeated_entries > 0 && !options.allow_repeated_entries {
         return None;
     }
 
-    // Compute three extra sums.
-    let S1 = a * a + d * d + g * g;
-    let S3 = i_val * i_val + f * f + c * c;
-    let S6 = c * c + e * e + g * g;
+    // The square will be reported: build the report of its incorrect axes. All 8 axes are checked, even the 5
+    // that are correct by construction.
+    let incorrect_axes = incorrect_axes(&assignment, N);
+    Some(Solution {
+        N,
+        a,
+        b,
+        c,
+        d,
+        e,
+        f,
+        g,
+        h,
+        i: i_val,
+        // If all axes are correct and all values are distinct, it's a perfect solution.
+        solution_type: if repeated_entries > 0 {
+            SolutionType::RepeatedEntries
+        } else if incorrect_axes.is_empty() {
+            SolutionType::Perfect
+        } else {
+            SolutionType::Partial
+        },
+        incorrect_axes,
+        repeated_entries,
+        zero_entries: assignment.iter().filter(|&&x| x == 0).count(),
+    })
+}
 
-    let mut incorrect_axes = vec![];
-    if S1 != N {
-        incorrect_axes.push(S1);
-    }
-    if S3 != N {
-        incorrect_axes.push(S3);
-    }
-    if S6 != N {
-        incorrect_axes.push(S6);
-    }
+/// One of the 8 lines (rows, columns and diagonals) of a 3x3 square.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Axis {
+    TopRow,
+    MiddleRow,
+    BottomRow,
+    LeftColumn,
+    MiddleColumn,
+    RightColumn,
+    /// Top-left to bottom-right diagonal (a, e, i).
+    MainDiagonal,
+    /// Bottom-left to top-right diagonal (g, e, c).
+    AntiDiagonal,
+}
 
-    let correct_axes = 3 - incorrect_axes.len();
+/// The 8 axes of a 3x3 square, with the indices of their cells in row-major order.
+pub(crate) const AXES: [(Axis, [usize; 3]); 8] = [
+    (Axis::TopRow, [0, 1, 2]),
+    (Axis::MiddleRow, [3, 4, 5]),
+    (Axis::BottomRow, [6, 7, 8]),
+    (Axis::LeftColumn, [0, 3, 6]),
+    (Axis::MiddleColumn, [1, 4, 7]),
+    (Axis::RightColumn, [2, 5, 8]),
+    (Axis::MainDiagonal, [0, 4, 8]),
+    (Axis::AntiDiagonal, [2, 4, 6]),
+];
 
-    if correct_axes >= 2 {
-        return Option::from(Solution {
-            N,
-            a,
-            b,
-            c,
-            d,
-            e,
+/// Report for an axis whose sum of squares differs from N.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AxisReport {
+    pub axis: Axis,
+    /// Sum of the squares of the 3 cells of the axis.
+    pub sum: u128,
+    /// Difference between the sum and N (negative if the sum is below N).
+    pub deviation: i128,
+}
+
+impl fmt::Display for AxisReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
             f,
-            g,
-            h,
-            i: i_val,
-            // If all axes are correct, it's a perfect solution.
-            solution_type: if correct_axes == 3 {
-                SolutionType::Perfect
-            } else {
-                SolutionType::Partial
-            },
-            incorrect_axis_values: incorrect_axes,
-        });
+            "{:?} = {} (deviation {})",
+            self.axis, self.sum, self.deviation
+        )
     }
+}
+
+/// Returns x² without overflow. Cells are at most √N, so their squares always fit in a u128.
+fn sq(x: u64) -> u128 {
+    (x as u128) * (x as u128)
+}
 
-    None
+/// Returns a report for every axis of the square (given as its 9 cells in row-major order)
+/// whose sum of squares is not equal to N.
+fn incorrect_axes(grid: &[u64; 9], N: u128) -> Vec<AxisReport> {
+    let mut reports = vec![];
+    for &(axis, cells) in AXES.iter() {
+        let sum: u128 = cells.iter().map(|&idx| sq(grid[idx])).sum();
+        if sum != N {
+            reports.push(AxisReport {
+                axis,
+                sum,
+                deviation: sum as i128 - N as i128,
+            });
+        }
+    }
+    reports
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum SolutionType {
     Perfect,
     Partial,
+    /// Rows and columns are correct, but neither diagonal is.
+    SemiMagic,
+    /// Some of the 9 values are repeated (the axes may or may not be correct).
+    RepeatedEntries,
 }
 
+#[derive(Clone, Debug)]
 pub struct Solution {
-    pub N: u64,
+    pub N: u128,
     pub a: u64,
     pub b: u64,
     pub c: u64,
@@ -122,48 +206,259 @@ pub struct Solution {
     pub h: u64,
     pub i: u64,
     pub solution_type: SolutionType,
-    pub incorrect_axis_values: Vec<u64>,
+    /// Every axis whose sum of squares is not equal to N.
+    pub incorrect_axes: Vec<AxisReport>,
+    /// Number of values that are duplicates of another value (9 minus the number of distinct values).
+    pub repeated_entries: usize,
+    /// Number of entries equal to 0 (always 0 with `ZeroPolicy::StrictlyPositive`).
+    pub zero_entries: usize,
+}
+
+/// The 8 symmetries of a 3x3 square (dihedral group D4), as permutations of the cells `a..i` in row-major order.
+/// Applying a symmetry maps a square to an equivalent square with the same axis sums.
+const D4_SYMMETRIES: [[usize; 9]; 8] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8], // Identity
+    [6, 3, 0, 7, 4, 1, 8, 5, 2], // Rotation by 90°
+    [8, 7, 6, 5, 4, 3, 2, 1, 0], // Rotation by 180°
+    [2, 5, 8, 1, 4, 7, 0, 3, 6], // Rotation by 270°
+    [2, 1, 0, 5, 4, 3, 8, 7, 6], // Left-right reflection
+    [6, 7, 8, 3, 4, 5, 0, 1, 2], // Top-bottom reflection
+    [0, 3, 6, 1, 4, 7, 2, 5, 8], // Reflection along the main diagonal
+    [8, 5, 2, 7, 4, 1, 6, 3, 0], // Reflection along the anti-diagonal
+];
+
+impl Solution {
+    /// Rebuilds the solution for the square with the 9 cells `grid` (in row-major order) and magic sum N,
+    /// e.g. when it is read back from a checkpoint file. Its axes and type are recomputed from the grid.
+    pub fn from_grid(N: u128, grid: [u64; 9]) -> Solution {
+        let [a, b, c, d, e, f, g, h, i] = grid;
+        let unique: FxHashSet<_> = grid.iter().cloned().collect();
+        let repeated_entries = 9 - unique.len();
+        let incorrect_axes = incorrect_axes(&grid, N);
+        Solution {
+            N,
+            a,
+            b,
+            c,
+            d,
+            e,
+            f,
+            g,
+            h,
+            i,
+            solution_type: if repeated_entries > 0 {
+                SolutionType::RepeatedEntries
+            } else if incorrect_axes.is_empty() {
+                SolutionType::Perfect
+            } else {
+                SolutionType::Partial
+            },
+            incorrect_axes,
+            repeated_entries,
+            zero_entries: grid.iter().filter(|&&x| x == 0).count(),
+        }
+    }
+
+    /// Returns the 9 cells of the square in row-major order.
+    pub fn grid(&self) -> [u64; 9] {
+        [
+            self.a, self.b, self.c, self.d, self.e, self.f, self.g, self.h, self.i,
+        ]
+    }
+
+    /// Returns the canonical representative of the square under rotations and reflections:
+    /// the lexicographically smallest grid among its 8 symmetric variants.
+    pub fn canonical_grid(&self) -> [u64; 9] {
+        let grid = self.grid();
+        D4_SYMMETRIES
+            .iter()
+            .map(|sym| sym.map(|idx| grid[idx]))
+            .min()
+            .unwrap()
+    }
+
+    /// Returns the same square, rotated/reflected into its canonical orientation.
+    /// The incorrect axes are recomputed, as the symmetry moves them.
+    pub fn canonicalize(&self) -> Solution {
+        let grid = self.canonical_grid();
+        let [a, b, c, d, e, f, g, h, i] = grid;
+        Solution {
+            a,
+            b,
+            c,
+            d,
+            e,
+            f,
+            g,
+            h,
+            i,
+            incorrect_axes: incorrect_axes(&grid, self.N),
+            ..self.clone()
+        }
+    }
+
+    /// Formats the incorrect axes for display, e.g. `[AntiDiagonal = 10092 (deviation -11517)]`.
+    pub fn describe_incorrect_axes(&self) -> String {
+        let axes: Vec<String> = self.incorrect_axes.iter().map(|a| a.to_string()).collect();
+        format!("[{}]", axes.join(", "))
+    }
+}
+
+/// Two solutions are equal if they are the same square up to rotations and reflections.
+impl PartialEq for Solution {
+    fn eq(&self, other: &Self) -> bool {
+        self.N == other.N && self.canonical_grid() == other.canonical_grid()
+    }
+}
+
+impl Eq for Solution {}
+
+impl Hash for Solution {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.N.hash(state);
+        self.canonical_grid().hash(state);
+    }
 }
 
 /// Finds “perfect squares” (i.e. candidate magic squares) for a given N.
+/// Returns the first square found with at most `options.max_incorrect_axes` incorrect axes.
+/// Panics if N is above `MAX_N` (see `conjecture::N_from_kp` to build N with a range check).
 pub fn find_perfect_squares(
-    perfect_squares_precomp: Option<&PrecomputedPerfectSquares>,
-    precomputed_square_sums: Option<&FxHashMap<u64, Box<[(u64, u64)]>>>,
-    N: u64,
+    options: &SearchOptions,
+    precomputed_square_sums: Option<&SquareSumTable>,
+    N: u128,
 ) -> Option<Solution> {
+    let mut found = None;
+    search_squares(options, precomputed_square_sums, N, |solution| {
+        found = Some(solution);
+        ControlFlow::Break(())
+    });
+    found
+}
+
+/// Runs `find_perfect_squares` for every N in [n_min, n_max), in parallel or not, and returns the squares found
+/// sorted by N. The table is only read, so a single copy is shared by reference between the rayon workers,
+/// and both modes run exactly the same search.
+pub fn find_perfect_squares_in_range(
+    options: &SearchOptions,
+    precomputed_square_sums: Option<&SquareSumTable>,
+    n_min: u64,
+    n_max: u64,
+    parallel: bool,
+) -> Vec<Solution> {
+    let find_solution = |n: u64| find_perfect_squares(options, precomputed_square_sums, n as u128);
+    let mut solutions: Vec<Solution> = if parallel {
+        (n_min..n_max)
+            .into_par_iter()
+            .filter_map(find_solution)
+            .collect()
+    } else {
+        (n_min..n_max).filter_map(find_solution).collect()
+    };
+    solutions.sort_by_key(|sol| sol.N);
+    solutions
+}
+
+/// Finds every (partial or perfect) magic square for a given N, instead of stopping at the first one.
+/// Walks every `e`, every ordering of the pairs and every flipped variant.
+/// Each square is returned once, in its canonical orientation (see `Solution::canonicalize`).
+/// Panics if N is above `MAX_N`.
+pub fn find_all_perfect_squares(
+    options: &SearchOptions,
+    precomputed_square_sums: Option<&SquareSumTable>,
+    N: u128,
+) -> Vec<Solution> {
+    let mut seen: FxHashSet<Solution> = FxHashSet::default();
+    let mut found = vec![];
+    search_squares(options, precomputed_square_sums, N, |solution| {
+        let solution = solution.canonicalize();
+        if seen.insert(solution.clone()) {
+            found.push(solution);
+        }
+        ControlFlow::Continue(())
+    });
+    found
+}
+
+/// Returns the window min_X..=max_X of the values looked up in the sum-of-two-squares table
+/// when searching all N in [n_min, n_max), i.e. the X = N - e² for every e tried by the search.
+pub fn square_sum_lookup_window(options: &SearchOptions, n_min: u64, n_max: u64) -> (u64, u64) {
+    // e² <= N/3 (see `search_squares`), unless the center can be larger with repeated entries.
+    // X = 0 is never looked up (the table starts at 1).
+    let min_X = if options.allow_repeated_entries {
+        1
+    } else {
+        (n_min - n_min / 3).max(1)
+    };
+    (min_X, n_max.saturating_sub(1))
+}
+
+/// Returns the orderings of three pairs (a,i), (b,h) and (d,f) to try, among the pairs (x, y) with x² + y² = X.
+fn pair_orderings<P: SquarePair>(
+    options: &SearchOptions,
+    pairs_list: &[P],
+) -> Vec<((u64, u64), (u64, u64), (u64, u64))> {
+    // Three distinct pairs are needed for (a,i), (b,h) and (d,f), once the (0, √X) pair is dropped if 0 is not allowed.
+    let pairs_list = generate_squares::apply_zero_policy(pairs_list, options.zero_policy);
+    if !options.allow_repeated_entries && pairs_list.len() < 3 {
+        return vec![];
+    }
+
+    // For each non-symmetric permutation of three pairs...
+    // Repeated entries can come from using the same pair twice, so the pairs are not required to be distinct.
+    if options.allow_repeated_entries {
+        utils::nonsymetric_permutations_with_repetition_3(pairs_list)
+    } else {
+        utils::nonsymetric_permutations_3(pairs_list)
+    }
+}
+
+/// Walks the search space for a given N and calls `on_solution` for each square found.
+/// The search stops as soon as `on_solution` returns `ControlFlow::Break`.
+fn search_squares(
+    options: &SearchOptions,
+    precomputed_square_sums: Option<&SquareSumTable>,
+    N: u128,
+    mut on_solution: impl FnMut(Solution) -> ControlFlow<()>,
+) {
+    assert!(N <= MAX_N, "N = {} is out of range", N);
+
     // Check if N can be written as the sum of three squares. If it can't, then no magic square can be formed.
     if !utils::can_be_written_as_sum_of_three_squares(N) {
-        return None;
+        return;
     }
 
     // Maximum value for any of the 9 numbers.
     // Max value for e is sqrt(N/3), since N is the sum of three squares.
-    let max_val = num_integer::sqrt(N / 3);
+    // With repeated entries, the center can be larger (the Parker square has 37² > N/3), so we go up to sqrt(N).
+    let max_val = if options.allow_repeated_entries {
+        num_integer::sqrt(N) as u64
+    } else {
+        num_integer::sqrt(N / 3) as u64
+    };
 
     // Store all e tested because we know that if we try a a->e->i diagonal, if e ever takes `a` or `i` later,
     // it will be a duplicate of the same square (with some translations).
     let mut tested_es: FxHashSet<u64> = FxHashSet::default();
 
     // Loop over candidate extra number e (with e² <= N/3).
-    for e in 1..max_val {
-        let X = N - e * e;
+    let min_val = if options.zero_policy.allows(0) { 0 } else { 1 };
+    for e in min_val..max_val {
+        let X = N - sq(e);
 
         // We want the pairs (a,i), (b,h), (d,f) to satisfy x²+y² = X.
-        // Get from precomputed_square_sums if possible
-        let pairs_list = match precomputed_square_sums {
-            Some(prec) => match prec.get(&X) {
-                Some(pairs) => pairs,
+        // Get from precomputed_square_sums if possible (borrowed from the table, without copying them).
+        let orderings = match precomputed_square_sums {
+            Some(prec) => match u64::try_from(X).ok().and_then(|X| prec.get(X)) {
+                Some(pairs) => pair_orderings(options, pairs),
                 None => continue,
             },
-            None => &generate_squares::find_sum_of_squares_pairs(X).into_boxed_slice(),
+            None => pair_orderings(
+                options,
+                &generate_squares::find_sum_of_squares_pairs_u128(X),
+            ),
         };
 
-        if pairs_list.len() <= 3 {
-            continue;
-        }
-
-        // For each non-symmetric permutation of three pairs...
-        let orderings = utils::nonsymetric_permutations_3(&pairs_list);
         for ordering in orderings {
             // Create the four full orderings (reversing some of the pairs)
             let (p1, p2, p3) = ordering;
@@ -185,14 +480,392 @@ pub fn find_perfect_squares(
                 [p1, (p2.1, p2.0), (p3.1, p3.0)],
             ];
             for o in full_orderings {
-                match is_magic_square(perfect_squares_precomp, &o, e, N, X) {
-                    Some(solution) => {
-                        return Some(solution);
+                if let Some(solution) = is_magic_square(options, &o, e, N) {
+                    if on_solution(solution).is_break() {
+                        return;
                     }
-                    None => {}
                 }
             }
         }
     }
-    None
+}
+
+/// Returns all ordered pairs (x, y) of strictly positive integers such that x² + y² = X,
+/// i.e. both (x, y) and (y, x) are returned.
+fn ordered_positive_pairs(
+    precomputed_square_sums: Option<&SquareSumTable>,
+    X: u128,
+) -> Vec<(u64, u64)> {
+    let pairs = match precomputed_square_sums {
+        Some(prec) => match u64::try_from(X).ok().and_then(|X| prec.get(X)) {
+            Some(pairs) => pairs.iter().map(|&pair| pair.widen()).collect(),
+            None => return vec![],
+        },
+        None => generate_squares::find_sum_of_squares_pairs_u128(X),
+    };
+    pairs
+        .into_iter()
+        .filter(|&(x, _)| x > 0)
+        .flat_map(|(x, y)| {
+            if x == y {
+                vec![(x, y)]
+            } else {
+                vec![(x, y), (y, x)]
+            }
+        })
+        .collect()
+}
+
+/// The 6 permutations of 3 rows (or columns).
+const PERMUTATIONS_3: [[usize; 3]; 6] = [
+    [0, 1, 2],
+    [0, 2, 1],
+    [1, 0, 2],
+    [1, 2, 0],
+    [2, 0, 1],
+    [2, 1, 0],
+];
+
+/// Returns the arrangement of a semi-magic square (among the 36 permutations of its rows and columns, which keep
+/// the rows and columns correct) with the fewest incorrect diagonals. Transposing does not change the diagonals.
+fn best_semi_magic_arrangement(grid: &[u64; 9], N: u128) -> [u64; 9] {
+    PERMUTATIONS_3
+        .iter()
+        .flat_map(|rows| {
+            PERMUTATIONS_3.iter().map(move |columns| {
+                std::array::from_fn(|idx| grid[3 * rows[idx / 3] + columns[idx % 3]])
+            })
+        })
+        .min_by_key(|arrangement: &[u64; 9]| incorrect_axes(arrangement, N).len())
+        .unwrap()
+}
+
+/// Finds every semi-magic square of squares for a given N: all rows and columns sum to N,
+/// the diagonals are free. The diagonals that do not match are reported in `incorrect_axes`.
+///
+/// Semi-magic squares are invariant under any permutation of rows, any permutation of columns and transposition.
+/// We only search one representative per class (`a` is the smallest value, `b < c`, `d < g` and `b < d`),
+/// but the diagonals depend on the arrangement, so each class is reported in the arrangement with the fewest
+/// incorrect diagonals (see `best_semi_magic_arrangement`): a class containing a magic square is reported as Perfect.
+/// Squares containing 0 are only searched if `zero_policy` allows it. Panics if N is above `MAX_N`.
+pub fn find_semi_magic_squares(
+    zero_policy: ZeroPolicy,
+    precomputed_square_sums: Option<&SquareSumTable>,
+    N: u128,
+) -> Vec<Solution> {
+    assert!(N <= MAX_N, "N = {} is out of range", N);
+    let mut solutions = vec![];
+
+    // a is the smallest value, so a² + b² + c² = N gives a² < N/3.
+    let max_val = num_integer::sqrt(N / 3) as u64;
+    // All the other values are larger than a, so only a can be 0.
+    let min_val = if zero_policy.allows(0) { 0 } else { 1 };
+
+    for a in min_val..=max_val {
+        // Top row (a, b, c) and left column (a, d, g) both come from the pairs summing to N - a².
+        let outer_pairs = ordered_positive_pairs(precomputed_square_sums, N - sq(a));
+
+        for &(b, c) in outer_pairs.iter().filter(|&&(b, c)| a < b && b < c) {
+            for &(d, g) in outer_pairs.iter().filter(|&&(d, g)| b < d && d < g) {
+                // Middle row: e² + f² = N - d².
+                for (e, f) in ordered_positive_pairs(precomputed_square_sums, N - sq(d)) {
+                    if e <= a || f <= a {
+                        continue;
+                    }
+
+                    // Middle column: h² = N - b² - e².
+                    let sum_be = sq(b) + sq(e);
+                    if sum_be >= N {
+                        continue;
+                    }
+                    let (is_ps_h, h) = generate_squares::is_perfect_square_u128(N - sum_be);
+                    if !is_ps_h || h <= a {
+                        continue;
+                    }
+
+                    // Right column: i² = N - c² - f².
+                    let sum_cf = sq(c) + sq(f);
+                    if sum_cf >= N {
+                        continue;
+                    }
+                    let (is_ps_i, i) = generate_squares::is_perfect_square_u128(N - sum_cf);
+                    if !is_ps_i || i <= a {
+                        continue;
+                    }
+
+                    // Bottom row is the only one not correct by construction.
+                    if sq(g) + sq(h) + sq(i) != N {
+                        continue;
+                    }
+
+                    let grid = [a, b, c, d, e, f, g, h, i];
+                    let unique: FxHashSet<_> = grid.iter().cloned().collect();
+                    if unique.len() < 9 {
+                        continue;
+                    }
+
+                    // The diagonals are not invariant under the permutations of the class: report its best arrangement.
+                    let mut solution =
+                        Solution::from_grid(N, best_semi_magic_arrangement(&grid, N));
+                    if solution.incorrect_axes.len() == 2 {
+                        solution.solution_type = SolutionType::SemiMagic;
+                    }
+                    solutions.push(solution);
+                }
+            }
+        }
+    }
+
+    solutions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_form_is_invariant_under_symmetries() {
+        let grid = [74, 127, 2, 97, 58, 94, 82, 46, 113];
+        let original = Solution::from_grid(21609, grid);
+        let canonical = original.canonical_grid();
+
+        for sym in D4_SYMMETRIES {
+            let transformed = Solution::from_grid(21609, sym.map(|idx| grid[idx]));
+            assert_eq!(transformed.canonical_grid(), canonical);
+            assert!(transformed == original);
+        }
+
+        let unique: FxHashSet<Solution> = D4_SYMMETRIES
+            .iter()
+            .map(|sym| Solution::from_grid(21609, sym.map(|idx| grid[idx])))
+            .collect();
+        assert_eq!(unique.len(), 1);
+    }
+
+    #[test]
+    fn test_incorrect_axes_reports_position() {
+        let solution = find_perfect_squares(&SearchOptions::default(), None, 21609).unwrap();
+        assert_eq!(
+            solution.incorrect_axes,
+            vec![AxisReport {
+                axis: Axis::AntiDiagonal,
+                sum: 10092,
+                deviation: 10092 - 21609,
+            }]
+        );
+
+        // Rotating the square by 90° turns the anti-diagonal into the main diagonal.
+        let rotated = Solution::from_grid(21609, D4_SYMMETRIES[1].map(|idx| solution.grid()[idx]));
+        assert_eq!(rotated.incorrect_axes[0].axis, Axis::MainDiagonal);
+        assert_eq!(rotated.incorrect_axes[0].sum, 10092);
+    }
+
+    #[test]
+    fn test_max_incorrect_axes() {
+        let perfect_only = SearchOptions {
+            max_incorrect_axes: 0,
+            ..SearchOptions::default()
+        };
+        assert!(find_perfect_squares(&perfect_only, None, 21609).is_none());
+
+        let with_max = |max_incorrect_axes, N| {
+            let options = SearchOptions {
+                max_incorrect_axes,
+                ..SearchOptions::default()
+            };
+            find_all_perfect_squares(&options, None, N)
+        };
+        assert!(with_max(0, 21609).is_empty());
+
+        // The quasi magic square only has its anti-diagonal wrong.
+        let quasi = Solution::from_grid(21609, [74, 127, 2, 97, 58, 94, 82, 46, 113]);
+        assert_eq!(quasi.incorrect_axes.len(), 1);
+        assert!(with_max(1, 21609).contains(&quasi));
+
+        // This square has 3 incorrect axes: it is only found once 3 are allowed.
+        let three_lines_off = Solution::from_grid(750, [2, 11, 25, 17, 10, 19, 5, 23, 14]);
+        assert_eq!(three_lines_off.incorrect_axes.len(), 3);
+        assert!(!with_max(2, 750).contains(&three_lines_off));
+        assert!(with_max(3, 750).contains(&three_lines_off));
+        assert!(with_max(2, 750)
+            .iter()
+            .all(|sol| sol.incorrect_axes.len() <= 2));
+    }
+
+    #[test]
+    fn test_find_semi_magic_squares() {
+        // Smallest semi-magic square of squares with distinct entries (magic sum 57²).
+        let solutions = find_semi_magic_squares(ZeroPolicy::StrictlyPositive, None, 3249);
+        let expected = Solution::from_grid(3249, [4, 23, 52, 32, 44, 17, 47, 28, 16]);
+        assert_eq!(solutions, vec![expected]);
+        assert_eq!(solutions[0].solution_type, SolutionType::SemiMagic);
+        assert_eq!(solutions[0].incorrect_axes.len(), 2);
+
+        // The quasi magic square is found in an arrangement with a single incorrect diagonal.
+        let mut quasi_entries = [74, 127, 2, 97, 58, 94, 82, 46, 113];
+        quasi_entries.sort_unstable();
+        let solutions = find_semi_magic_squares(ZeroPolicy::StrictlyPositive, None, 21609);
+        let found = solutions
+            .iter()
+            .find(|sol| {
+                let mut entries = sol.grid();
+                entries.sort_unstable();
+                entries == quasi_entries
+            })
+            .unwrap();
+        assert_eq!(found.solution_type, SolutionType::Partial);
+        assert_eq!(found.incorrect_axes.len(), 1);
+
+        // Squares containing 0 are only found when allowed.
+        let with_zero = Solution::from_grid(4225, [0, 25, 60, 39, 48, 20, 52, 36, 15]);
+        assert!(
+            !find_semi_magic_squares(ZeroPolicy::StrictlyPositive, None, 4225).contains(&with_zero)
+        );
+        let solutions = find_semi_magic_squares(ZeroPolicy::NonNegative, None, 4225);
+        assert!(solutions.contains(&with_zero));
+        assert_eq!(
+            solutions.iter().filter(|sol| sol.zero_entries == 1).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_best_semi_magic_arrangement() {
+        // Swapping two rows of the quasi magic square breaks both diagonals; the best arrangement fixes one.
+        let grid = [97, 58, 94, 74, 127, 2, 82, 46, 113];
+        assert_eq!(incorrect_axes(&grid, 21609).len(), 2);
+        let best = best_semi_magic_arrangement(&grid, 21609);
+        assert_eq!(incorrect_axes(&best, 21609).len(), 1);
+    }
+
+    #[test]
+    fn test_allow_repeated_entries_finds_parker_square() {
+        let parker_square = Solution::from_grid(3051, [29, 1, 47, 41, 37, 1, 23, 41, 29]);
+
+        let distinct = find_all_perfect_squares(&SearchOptions::default(), None, 3051);
+        assert!(!distinct.contains(&parker_square));
+
+        let options = SearchOptions {
+            allow_repeated_entries: true,
+            ..SearchOptions::default()
+        };
+        let solutions = find_all_perfect_squares(&options, None, 3051);
+        let found = solutions.iter().find(|&sol| *sol == parker_square).unwrap();
+        assert_eq!(found.solution_type, SolutionType::RepeatedEntries);
+        assert_eq!(found.repeated_entries, 3);
+        assert_eq!(found.incorrect_axes.len(), 1);
+    }
+
+    #[test]
+    fn test_zero_policy() {
+        let non_negative = SearchOptions {
+            allow_repeated_entries: true,
+            zero_policy: ZeroPolicy::NonNegative,
+            ..SearchOptions::default()
+        };
+        let with_zero = find_all_perfect_squares(&non_negative, None, 25);
+        assert!(with_zero.iter().any(|sol| sol.zero_entries > 0));
+        assert!(with_zero
+            .iter()
+            .all(|sol| sol.zero_entries == sol.grid().iter().filter(|&&x| x == 0).count()));
+
+        let strictly_positive = SearchOptions {
+            zero_policy: ZeroPolicy::StrictlyPositive,
+            ..non_negative
+        };
+        let without_zero = find_all_perfect_squares(&strictly_positive, None, 25);
+        assert!(without_zero.iter().all(|sol| !sol.grid().contains(&0)));
+        assert!(without_zero.len() < with_zero.len());
+    }
+
+    #[test]
+    fn test_find_all_perfect_squares_uses_values_with_three_pairs() {
+        // 750 - 10² = 650 = 11² + 23² = 17² + 19² = 5² + 25² has exactly the 3 pairs needed around e = 10.
+        let options = SearchOptions {
+            max_incorrect_axes: 3,
+            ..SearchOptions::default()
+        };
+        let solutions = find_all_perfect_squares(&options, None, 750);
+        let expected = Solution::from_grid(750, [2, 11, 25, 17, 10, 19, 5, 23, 14]);
+        assert!(solutions.contains(&expected));
+        assert_eq!(solutions.iter().filter(|sol| sol.e == 10).count(), 4);
+    }
+
+    #[test]
+    fn test_is_magic_square_above_u64() {
+        // The quasi magic square for 21609, scaled so that N = 21609·k² does not fit in a u64.
+        let k = 1u64 << 50;
+        let N = 21609 * (k as u128) * (k as u128);
+        assert!(N > u64::MAX as u128 && N <= MAX_N);
+        let ordering = [(74 * k, 113 * k), (127 * k, 46 * k), (97 * k, 94 * k)];
+        let solution = is_magic_square(&SearchOptions::default(), &ordering, 58 * k, N).unwrap();
+        assert_eq!(
+            solution.grid(),
+            [74, 127, 2, 97, 58, 94, 82, 46, 113].map(|x| x * k)
+        );
+        assert_eq!(solution.incorrect_axes.len(), 1);
+        assert_eq!(
+            solution.incorrect_axes[0].deviation,
+            (10092 - 21609) * (k as i128) * (k as i128)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn test_search_rejects_N_above_max() {
+        find_perfect_squares(&SearchOptions::default(), None, MAX_N + 1);
+    }
+
+    #[test]
+    fn test_find_all_perfect_squares_has_no_duplicates() {
+        let solutions = find_all_perfect_squares(&SearchOptions::default(), None, 21609);
+        let unique: FxHashSet<&Solution> = solutions.iter().collect();
+        assert!(!solutions.is_empty());
+        assert_eq!(unique.len(), solutions.len());
+    }
+
+    #[test]
+    fn test_square_sum_lookup_window() {
+        let full = generate_squares::generate_squares_sum_fast(25_000).unwrap();
+        for options in [
+            SearchOptions::default(),
+            SearchOptions {
+                max_incorrect_axes: 2,
+                allow_repeated_entries: true,
+                ..SearchOptions::default()
+            },
+        ] {
+            let (n_min, n_max) = (21_000, 22_000);
+            let (min_X, max_X) = square_sum_lookup_window(&options, n_min, n_max);
+            let window = generate_squares::generate_squares_sum_window(min_X, max_X).unwrap();
+            for N in n_min..n_max {
+                assert_eq!(
+                    find_all_perfect_squares(&options, Some(&window), N as u128),
+                    find_all_perfect_squares(&options, Some(&full), N as u128),
+                    "N = {}",
+                    N
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_and_sequential_searches_match() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<SquareSumTable>();
+
+        let table = generate_squares::generate_squares_sum_fast(60_000).unwrap();
+        let options = SearchOptions {
+            max_incorrect_axes: 2,
+            ..SearchOptions::default()
+        };
+        let parallel = find_perfect_squares_in_range(&options, Some(&table), 0, 60_000, true);
+        let sequential = find_perfect_squares_in_range(&options, Some(&table), 0, 60_000, false);
+        assert!(!parallel.is_empty());
+        assert_eq!(parallel, sequential);
+        assert!(parallel
+            .iter()
+            .zip(&sequential)
+            .all(|(p, s)| p.grid() == s.grid() && p.incorrect_axes == s.incorrect_axes));
+    }
 }
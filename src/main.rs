@@ -1,124 +1,746 @@
 #![allow(warnings)]
 
+use fxhash::{FxHashMap, FxHashSet};
 use rayon::prelude::*;
 use std::time::Instant;
 
+#[cfg(feature = "bigint")]
+mod big_squares;
+mod checkpoint;
 mod conjecture;
+mod factorization;
 mod generate_squares;
+mod magic_grids;
 mod magic_squares;
+mod parametrization;
+mod table_file;
 mod utils;
 
+/// Precompute the sum-of-two-squares table up to n_max, and report its memory usage.
+fn precompute_square_sums(n_max: u64) -> generate_squares::SquareSumTable {
+    let start = Instant::now();
+    let table = generate_squares::generate_squares_sum_fast(n_max).unwrap_or_else(|_| {
+        eprintln!(
+            "Too many sums of two squares up to {} for a single table, use --segment",
+            n_max
+        );
+        std::process::exit(1);
+    });
+    println!(
+        "Precomputed sums of two squares up to {}: {} representable values, {} pairs, {:.1} MiB ({:.2} seconds)",
+        n_max,
+        table.representable_count(),
+        table.pair_count(),
+        table.memory_usage() as f64 / (1024.0 * 1024.0),
+        start.elapsed().as_secs_f64()
+    );
+    table
+}
+
+/// Memory-map a table written by `precompute`, and check that it covers the values looked up for N in [n_min, n_max).
+fn load_square_sums(
+    path: &str,
+    verification: table_file::Verification,
+    options: &magic_squares::SearchOptions,
+    n_min: u64,
+    n_max: u64,
+) -> generate_squares::SquareSumTable {
+    let start = Instant::now();
+    let table = table_file::load(std::path::Path::new(path), verification).unwrap_or_else(|err| {
+        eprintln!("Cannot load {}: {}", path, err);
+        std::process::exit(1);
+    });
+    let (min_X, max_X) = magic_squares::square_sum_lookup_window(options, n_min, n_max);
+    if table.min_n() > min_X || table.max_n() < max_X {
+        eprintln!(
+            "{} covers {}..={}, but N = {}..{} needs {}..={}",
+            path,
+            table.min_n(),
+            table.max_n(),
+            n_min,
+            n_max,
+            min_X,
+            max_X
+        );
+        std::process::exit(1);
+    }
+    println!(
+        "Mapped sums of two squares in {}..={} from {}: {} pairs, {:.1} MiB ({:.2} seconds)",
+        table.min_n(),
+        table.max_n(),
+        path,
+        table.pair_count(),
+        table.memory_usage() as f64 / (1024.0 * 1024.0),
+        start.elapsed().as_secs_f64()
+    );
+    table
+}
+
+/// Precompute the sum-of-two-squares table up to n_max and write it to a file, to be used with `test_n --table=<file>`.
+fn precompute_table_file(n_max: u64, path: &str) {
+    let table = precompute_square_sums(n_max);
+    table_file::save(&table, std::path::Path::new(path)).unwrap_or_else(|err| {
+        eprintln!("Cannot write {}: {}", path, err);
+        std::process::exit(1);
+    });
+    println!("Table written to {}", path);
+}
+
+/// Times `is_perfect_square` against the lookup map it replaced (every square up to n_max with its root, like the
+/// former `PrecomputedPerfectSquares`), on all n in [n_min, n_max). Both must find the same squares.
+fn benchmark_perfect_square(n_min: u64, n_max: u64) {
+    let start = Instant::now();
+    let map: FxHashMap<u64, u64> = (0..=num_integer::sqrt(n_max)).map(|r| (r * r, r)).collect();
+    println!(
+        "Lookup map of the {} squares up to {}: built in {:.2} seconds",
+        map.len(),
+        n_max,
+        start.elapsed().as_secs_f64()
+    );
+
+    let start = Instant::now();
+    let map_roots: u64 = (n_min..n_max)
+        .filter_map(|n| map.get(&std::hint::black_box(n)))
+        .sum();
+    let map_time = start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    let test_roots: u64 = (n_min..n_max)
+        .map(|n| generate_squares::is_perfect_square(std::hint::black_box(n)))
+        .filter(|&(is_square, _)| is_square)
+        .map(|(_, r)| r)
+        .sum();
+    let test_time = start.elapsed().as_secs_f64();
+
+    assert_eq!(
+        map_roots, test_roots,
+        "the two tests found different squares"
+    );
+    println!(
+        "{} values: lookup map {:.2} seconds, is_perfect_square {:.2} seconds ({:.1}x)",
+        n_max.saturating_sub(n_min),
+        map_time,
+        test_time,
+        map_time / test_time
+    );
+}
+
+/// File where `test_n` writes the quasi magic squares that do not fit the conjecture, unless `--counterexamples` is given.
+const DEFAULT_COUNTEREXAMPLE_PATH: &str = "counterexamples.txt";
+
+/// Number of values of N per block of a checkpointed `test_n` run (see `checkpoint`).
+const CHECKPOINT_BLOCK_SIZE: u64 = 100_000;
+
+/// Number of (k, p) candidates per block of a checkpointed `test_kp` run.
+const KP_CHECKPOINT_BLOCK_SIZE: u64 = 16;
+
+/// How a long run records its progress: not at all, in a new checkpoint file, or resuming from an existing one.
+enum CheckpointMode {
+    None,
+    Create(String),
+    Resume(String),
+}
+
+impl CheckpointMode {
+    /// Opens the checkpoint file (if any) for the run described by `description`, or exits with an error.
+    fn open(&self, description: &str) -> Option<checkpoint::Checkpoint> {
+        let result = match self {
+            CheckpointMode::None => return None,
+            CheckpointMode::Create(path) => {
+                checkpoint::Checkpoint::create(std::path::Path::new(path), description)
+            }
+            CheckpointMode::Resume(path) => {
+                checkpoint::Checkpoint::resume(std::path::Path::new(path), description)
+            }
+        };
+        let checkpoint = result.unwrap_or_else(|err| {
+            eprintln!("Cannot open the checkpoint file: {}", err);
+            std::process::exit(1);
+        });
+        if checkpoint.completed_count() > 0 {
+            println!(
+                "Resuming from {}: {} blocks already completed",
+                checkpoint.path().display(),
+                checkpoint.completed_count()
+            );
+        }
+        Some(checkpoint)
+    }
+}
+
+/// Writes a completed block to the checkpoint file, or exits with an error.
+fn complete_checkpoint_block(
+    checkpoint: &mut checkpoint::Checkpoint,
+    start: u64,
+    end: u64,
+    records: Vec<String>,
+) {
+    checkpoint
+        .complete_block(start, end, records)
+        .unwrap_or_else(|err| {
+            eprintln!("Cannot write {}: {}", checkpoint.path().display(), err);
+            std::process::exit(1);
+        });
+}
+
 /// Test all values of N in the range [n_min, n_max). When `multiprocessing` is true,
-/// a parallel iterator is used.
-fn test_all_N_up_in_range(n_min: u64, n_max: u64, multiprocessing: bool) {
+/// a parallel iterator is used. `options` controls which squares are reported.
+/// The table is read from `table_path` if given (see `precompute`), else computed.
+/// With a checkpoint, the range is tested in blocks of `CHECKPOINT_BLOCK_SIZE` values of N, each recorded in the
+/// checkpoint file as soon as it is done; the blocks completed by a previous run are skipped, and their squares
+/// are reported together with the new ones.
+fn test_all_N_up_in_range(
+    n_min: u64,
+    n_max: u64,
+    multiprocessing: bool,
+    options: magic_squares::SearchOptions,
+    table: Option<(&str, table_file::Verification)>,
+    checkpoint_mode: CheckpointMode,
+    counterexample_path: &str,
+) {
     let start = Instant::now();
 
+    let description = format!(
+        "test_n {} {} {:?} blocks of {}",
+        n_min, n_max, options, CHECKPOINT_BLOCK_SIZE
+    );
+    let mut checkpoint = checkpoint_mode.open(&description);
+
     // Precompute common data.
-    let squares_sum = generate_squares::generate_squares_sum_fast(n_max);
-    let perfect_squares = generate_squares::precompute_perfect_squares(n_max);
+    let squares_sum = match table {
+        Some((path, verification)) => load_square_sums(path, verification, &options, n_min, n_max),
+        None => precompute_square_sums(n_max),
+    };
 
     println!("Checking N = {}..{}", n_min, n_max);
 
-    // Define a closure that attempts to find a solution for a given N.
-    // In the parallel branch we pass `None` for perfect_squares (if that's desired);
-    // otherwise we pass a reference.
-    let find_solution = |n: u64| {
-        let ps = if multiprocessing {
-            None
-        } else {
-            Some(&perfect_squares)
-        };
-        magic_squares::find_perfect_squares(ps, Some(&squares_sum), n)
+    // The table is shared read-only by all the workers: both modes run the same search.
+    let find_solutions = |block_min: u64, block_max: u64| {
+        magic_squares::find_perfect_squares_in_range(
+            &options,
+            Some(&squares_sum),
+            block_min,
+            block_max,
+            multiprocessing,
+        )
     };
+    let solutions = match checkpoint.as_mut() {
+        None => find_solutions(n_min, n_max),
+        Some(checkpoint) => {
+            let mut solutions = vec![];
+            for block_min in (n_min..n_max).step_by(CHECKPOINT_BLOCK_SIZE as usize) {
+                let block_max = (block_min + CHECKPOINT_BLOCK_SIZE).min(n_max);
+                if let Some(records) = checkpoint.completed_block(block_min, block_max) {
+                    for record in records {
+                        solutions.push(checkpoint::parse_solution_record(record).unwrap_or_else(
+                            |err| {
+                                eprintln!(
+                                    "Cannot resume from {}: {}",
+                                    checkpoint.path().display(),
+                                    err
+                                );
+                                std::process::exit(1);
+                            },
+                        ));
+                    }
+                    continue;
+                }
 
-    // Choose parallel or sequential processing.
-    let solutions: Vec<magic_squares::Solution> = if multiprocessing {
-        (n_min..n_max)
-            .into_par_iter()
-            .filter_map(find_solution)
-            .collect()
-    } else {
-        (n_min..n_max)
-            .into_iter()
-            .filter_map(find_solution)
-            .collect()
+                let found = find_solutions(block_min, block_max);
+                let records = found.iter().map(checkpoint::solution_record).collect();
+                complete_checkpoint_block(checkpoint, block_min, block_max, records);
+                println!(
+                    "Block N = {}..{} done: {} squares ({:.2} seconds)",
+                    block_min,
+                    block_max,
+                    found.len(),
+                    start.elapsed().as_secs_f64()
+                );
+                solutions.extend(found);
+            }
+            solutions
+        }
     };
+    let counterexamples = report_solutions(solutions);
+    report_counterexamples(&counterexamples, counterexample_path);
+
+    println!("Time: {:.2} seconds", start.elapsed().as_secs_f64());
+}
+
+/// Print a perfect magic square of squares. The run goes on, so that the following squares and the counterexamples
+/// are still reported.
+fn report_perfect_solution(sol: &magic_squares::Solution) {
+    println!(">>>> OMG WE FOUND A PERFECT SOLUTION!!! <<<<");
+    println!(">>>> WAKE UP HONEY A NEW SOLUTION JUST DROPPED <<<<");
+    println!("Perfect Solution for N = {}, e = {}", sol.N, sol.e);
+    println!(
+        "a = {}, b = {}, c = {}, d = {}, e = {}, f = {}, g = {}, h = {}, i = {}",
+        sol.a, sol.b, sol.c, sol.d, sol.e, sol.f, sol.g, sol.h, sol.i
+    );
+}
+
+/// Print the solutions found by `test_n` (sorted by N), and check the quasi magic squares against the conjecture.
+/// Returns the quasi magic squares that do not fit the conjecture, with their verdict.
+fn report_solutions(
+    mut responses: Vec<magic_squares::Solution>,
+) -> Vec<(magic_squares::Solution, conjecture::ConjectureVerdict)> {
+    let mut counterexamples = vec![];
 
     // Sort solutions by N.
-    let mut responses = solutions;
     responses.sort_by_key(|sol| sol.N);
 
     // Print each solution.
     for sol in responses {
         println!("------------------------------------------------------");
+        if sol.zero_entries > 0 {
+            println!("⚠️ This square contains {} zero entries", sol.zero_entries);
+        }
         if sol.solution_type == magic_squares::SolutionType::Perfect {
-            println!(">>>> OMG WE FOUND A PERFECT SOLUTION!!! <<<<");
-            println!(">>>> WAKE UP HONEY A NEW SOLUTION JUST DROPPED <<<<");
-            println!("Perfect Solution for N = {}, e = {}", sol.N, sol.e);
+            report_perfect_solution(&sol);
+        } else if sol.solution_type == magic_squares::SolutionType::RepeatedEntries {
+            println!(
+                "Magic square with {} repeated entries for N = {}, e = {}, incorrect axes = {}",
+                sol.repeated_entries,
+                sol.N,
+                sol.e,
+                sol.describe_incorrect_axes()
+            );
             println!(
                 "a = {}, b = {}, c = {}, d = {}, e = {}, f = {}, g = {}, h = {}, i = {}",
                 sol.a, sol.b, sol.c, sol.d, sol.e, sol.f, sol.g, sol.h, sol.i
             );
-            std::process::exit(0);
         } else {
             println!(
-                "Partial magic square for N = {}, e = {}, incorrect axes = {:?}",
-                sol.N, sol.e, sol.incorrect_axis_values
+                "Partial magic square for N = {}, e = {}, incorrect axes = {}",
+                sol.N,
+                sol.e,
+                sol.describe_incorrect_axes()
             );
             println!(
                 "a = {}, b = {}, c = {}, d = {}, e = {}, f = {}, g = {}, h = {}, i = {}",
                 sol.a, sol.b, sol.c, sol.d, sol.e, sol.f, sol.g, sol.h, sol.i
             );
 
-            match conjecture::verify_if_N_matches_conjecture(sol.N) {
-                Some((p, k)) => {
-                    println!("✅ N is in the form of (k*3*p²)² with p={} and k={}", p, k);
-                }
-                None => {
-                    println!("❌ N is not a multiple of (k*3*p²)²");
-                    panic!("N is not a multiple of (k*3*p²)²");
-                }
+            // The conjecture only concerns quasi magic squares (exactly one incorrect axis).
+            if sol.incorrect_axes.len() != 1 {
+                continue;
+            }
+
+            // Every decomposition is listed, as N can be of the form (k*3*p²)² for several primes p.
+            // A counterexample does not stop the run: they are all reported at the end.
+            let verdict = conjecture::check_conjecture(sol.N);
+            if verdict.fits() {
+                println!("✅ {}", verdict);
+            } else {
+                println!("❌ {}", verdict);
+                counterexamples.push((sol, verdict));
             }
         }
     }
+    counterexamples
+}
+
+/// Print the counterexamples to the conjecture found by a run, and write them to `path` (if there are any),
+/// one per line, in the format of the checkpoint files followed by the verdict.
+fn report_counterexamples(
+    counterexamples: &[(magic_squares::Solution, conjecture::ConjectureVerdict)],
+    path: &str,
+) {
+    println!("------------------------------------------------------");
+    if counterexamples.is_empty() {
+        println!("✅ Every quasi magic square fits the conjecture");
+        return;
+    }
+
+    let mut text = "# Quasi magic squares whose N is not of the form (k*3*p²)² with p prime and p ≡ 1 (mod 6)\n".to_string();
+    println!(
+        "😱 {} quasi magic squares do not fit the conjecture:",
+        counterexamples.len()
+    );
+    for (sol, verdict) in counterexamples {
+        println!("N = {}: {}", sol.N, verdict);
+        text += &format!("{} # {}\n", checkpoint::solution_record(sol), verdict);
+    }
+    match std::fs::write(path, text) {
+        Ok(()) => println!("Counterexamples written to {}", path),
+        Err(err) => eprintln!("Cannot write {}: {}", path, err),
+    }
+}
+
+/// Same as `test_all_N_up_in_range`, for blocks of `segment_size` values of N at a time.
+/// Only the values X = N - e² looked up by the search are precomputed for each block
+/// (see `magic_squares::square_sum_lookup_window`), and the window slides forward from one block to the next,
+/// so that ranges far from 0 can be tested without precomputing everything up to n_max.
+fn test_all_N_in_segments(
+    n_min: u64,
+    n_max: u64,
+    segment_size: u64,
+    multiprocessing: bool,
+    options: magic_squares::SearchOptions,
+    counterexample_path: &str,
+) {
+    let start = Instant::now();
+
+    println!(
+        "Checking N = {}..{} in segments of {}",
+        n_min, n_max, segment_size
+    );
+
+    let mut squares_sum: Option<generate_squares::SquareSumTable> = None;
+    let mut counterexamples = vec![];
+    for block_min in (n_min..n_max).step_by(segment_size as usize) {
+        let block_max = (block_min + segment_size).min(n_max);
+        let (min_X, max_X) =
+            magic_squares::square_sum_lookup_window(&options, block_min, block_max);
+        let table = match squares_sum.as_mut() {
+            Some(table) => table.slide(min_X, max_X).map(|_| table),
+            None => generate_squares::generate_squares_sum_window(min_X, max_X)
+                .map(|table| squares_sum.insert(table)),
+        }
+        .unwrap_or_else(|_| {
+            eprintln!(
+                "Too many sums of two squares in {}..={} for a single table, use a smaller --segment",
+                min_X, max_X
+            );
+            std::process::exit(1);
+        });
+        println!(
+            "Segment N = {}..{}: sums of two squares in {}..={} ({:.1} MiB)",
+            block_min,
+            block_max,
+            min_X,
+            max_X,
+            table.memory_usage() as f64 / (1024.0 * 1024.0)
+        );
+
+        let solutions = magic_squares::find_perfect_squares_in_range(
+            &options,
+            Some(table),
+            block_min,
+            block_max,
+            multiprocessing,
+        );
+        counterexamples.extend(report_solutions(solutions));
+    }
+    report_counterexamples(&counterexamples, counterexample_path);
+
+    println!("Time: {:.2} seconds", start.elapsed().as_secs_f64());
+}
+
+/// Test every hypothesis of `conjecture::hypotheses` against the quasi magic squares for N in [n_min, n_max),
+/// in both directions: does every N with a square fit the hypothesis, and does every fitting N have a square?
+/// Prints a summary table, followed by the first exceptions in each direction.
+fn compare_hypotheses(n_min: u64, n_max: u64) {
+    const SHOWN_EXCEPTIONS: usize = 5;
+    let start = Instant::now();
+
+    let squares_sum = precompute_square_sums(n_max);
+    println!("Searching quasi magic squares for N = {}..{}", n_min, n_max);
+    let with_square: FxHashSet<u128> = magic_squares::find_perfect_squares_in_range(
+        &magic_squares::SearchOptions::default(),
+        Some(&squares_sum),
+        n_min,
+        n_max,
+        true,
+    )
+    .iter()
+    .map(|sol| sol.N)
+    .collect();
+    let mut found: Vec<u128> = with_square.iter().copied().collect();
+    found.sort_unstable();
+    println!(
+        "Found {} values of N with a quasi magic square",
+        found.len()
+    );
+
+    println!("------------------------------------------------------");
+    println!(
+        "{:<14} {:<36} {:>12} {:>12} {:>12} {:>12}",
+        "hypothesis", "N", "squares fit", "don't fit", "fitting N", "no square"
+    );
+    let mut exceptions = vec![];
+    for hypothesis in conjecture::hypotheses() {
+        // Every square found fits the hypothesis...
+        let not_fitting: Vec<u128> = found
+            .iter()
+            .copied()
+            .filter(|&N| !hypothesis.fits(N))
+            .collect();
+        // ...and every fitting N has a square (all the N of the range have been searched).
+        let generated = hypothesis.generate(n_min as u128, n_max as u128);
+        let without_square: Vec<u128> = generated
+            .iter()
+            .copied()
+            .filter(|N| !with_square.contains(N))
+            .collect();
+        println!(
+            "{:<14} {:<36} {:>12} {:>12} {:>12} {:>12}",
+            hypothesis.name(),
+            hypothesis.formula(),
+            found.len() - not_fitting.len(),
+            not_fitting.len(),
+            generated.len(),
+            without_square.len()
+        );
+        exceptions.push((hypothesis.name().to_string(), not_fitting, without_square));
+    }
+
+    for (name, not_fitting, without_square) in exceptions {
+        let first = |values: &[u128]| {
+            let shown: Vec<String> = values
+                .iter()
+                .take(SHOWN_EXCEPTIONS)
+                .map(|N| N.to_string())
+                .collect();
+            shown.join(", ")
+        };
+        if !not_fitting.is_empty() {
+            println!("{}: squares that do not fit: {}", name, first(&not_fitting));
+        }
+        if !without_square.is_empty() {
+            println!(
+                "{}: fitting N without a square: {}",
+                name,
+                first(&without_square)
+            );
+        }
+    }
+
+    println!("Time: {:.2} seconds", start.elapsed().as_secs_f64());
+}
+
+/// Enumerate every (partial or perfect) magic square for all N in the range [n_min, n_max),
+/// and report how many squares were found for each N.
+fn enumerate_all_squares_in_range(n_min: u64, n_max: u64, options: magic_squares::SearchOptions) {
+    let start = Instant::now();
+
+    let squares_sum = precompute_square_sums(n_max);
+
+    println!("Enumerating N = {}..{}", n_min, n_max);
+
+    let mut responses: Vec<(u64, Vec<magic_squares::Solution>)> = (n_min..n_max)
+        .into_par_iter()
+        .map(|n| {
+            (
+                n,
+                magic_squares::find_all_perfect_squares(&options, Some(&squares_sum), n as u128),
+            )
+        })
+        .filter(|(_, sols)| !sols.is_empty())
+        .collect();
+
+    responses.sort_by_key(|(n, _)| *n);
+
+    for (n, sols) in responses {
+        println!("------------------------------------------------------");
+        println!("N = {} has {} squares", n, sols.len());
+        for sol in sols {
+            println!(
+                "a = {}, b = {}, c = {}, d = {}, e = {}, f = {}, g = {}, h = {}, i = {} (incorrect axes = {}, repeated entries = {}, zero entries = {})",
+                sol.a, sol.b, sol.c, sol.d, sol.e, sol.f, sol.g, sol.h, sol.i, sol.describe_incorrect_axes(), sol.repeated_entries, sol.zero_entries
+            );
+        }
+    }
+
+    println!("Time: {:.2} seconds", start.elapsed().as_secs_f64());
+}
+
+/// Search semi-magic squares of squares (rows and columns only) for all N in the range [n_min, n_max),
+/// and report which diagonals also match in the best arrangement of each square.
+fn semi_magic_squares_in_range(n_min: u64, n_max: u64, zero_policy: generate_squares::ZeroPolicy) {
+    let start = Instant::now();
+
+    let squares_sum = precompute_square_sums(n_max);
+
+    println!("Searching semi-magic squares for N = {}..{}", n_min, n_max);
+
+    let mut responses: Vec<(u64, Vec<magic_squares::Solution>)> = (n_min..n_max)
+        .into_par_iter()
+        .map(|n| {
+            (
+                n,
+                magic_squares::find_semi_magic_squares(zero_policy, Some(&squares_sum), n as u128),
+            )
+        })
+        .filter(|(_, sols)| !sols.is_empty())
+        .collect();
+
+    responses.sort_by_key(|(n, _)| *n);
+
+    for (n, sols) in responses {
+        println!("------------------------------------------------------");
+        println!("N = {} has {} semi-magic squares", n, sols.len());
+        for sol in sols {
+            if sol.solution_type == magic_squares::SolutionType::Perfect {
+                println!(">>>> OMG WE FOUND A PERFECT SOLUTION!!! <<<<");
+            }
+            println!(
+                "a = {}, b = {}, c = {}, d = {}, e = {}, f = {}, g = {}, h = {}, i = {} (incorrect diagonals = {})",
+                sol.a, sol.b, sol.c, sol.d, sol.e, sol.f, sol.g, sol.h, sol.i, sol.describe_incorrect_axes()
+            );
+        }
+    }
+
+    println!("Time: {:.2} seconds", start.elapsed().as_secs_f64());
+}
+
+/// Search all size×size magic squares of k-th powers (squares by default) with magic sum N.
+fn search_magic_grids(size: u64, N: u64, exponent: u32) {
+    let start = Instant::now();
+    let size = size as usize;
+
+    if let Err(err) = magic_grids::check_grid_range(size, N) {
+        println!(
+            "⚠️ N = {} is {} for a {}x{} grid: its line sums don't fit in a u64",
+            N, err, size, size
+        );
+        return;
+    }
+
+    // The precomputed table is only used for squares.
+    let squares_sum = (exponent == 2).then(|| precompute_square_sums(N));
+
+    println!(
+        "Searching {}x{} magic squares of powers of {} for N = {}",
+        size, size, exponent, N
+    );
+
+    // N is checked once here, before building the table: the searches below can't fail.
+    let semi_magic =
+        magic_grids::find_semi_magic_grids(squares_sum.as_ref(), size, exponent, N).unwrap();
+    println!(
+        "Found {} semi-magic squares (up to row and column permutations)",
+        semi_magic.len()
+    );
+
+    let magic = magic_grids::find_magic_grids(squares_sum.as_ref(), size, exponent, N).unwrap();
+    for grid in &magic {
+        println!("------------------------------------------------------");
+        print!("{}", grid);
+    }
+    println!(
+        "Found {} magic squares (up to rotations and reflections)",
+        magic.len()
+    );
 
     println!("Time: {:.2} seconds", start.elapsed().as_secs_f64());
 }
 
 /// Test all numbers of the form (k*3*p²)² with k in [1, max_k] and p prime in [1, max_p]
 /// (with the extra condition that p ≡ 1 (mod 6)).
-fn test_kp_form_up_to(max_k: u64, max_p: u64) {
+/// With a checkpoint, the candidates are tested in blocks of `KP_CHECKPOINT_BLOCK_SIZE` (see `test_all_N_up_in_range`).
+fn test_kp_form_up_to(max_k: u64, max_p: u64, checkpoint_mode: CheckpointMode) {
     let start = Instant::now();
 
-    // Generate candidate (k, p, N) tuples.
-    let candidates: Vec<(u64, u64, u64)> = (1..=max_k)
+    // Generate candidate (k, p, N) tuples. N is out of range if it would overflow.
+    let candidates: Vec<(u64, u64, Result<u128, utils::OutOfRange>)> = (1..=max_k)
         .flat_map(|k| {
             (1..=max_p)
                 .filter(|&p| utils::is_prime(p) && p % 6 == 1)
-                .map(move |p| (k, p, (k * 3 * p.pow(2)).pow(2)))
+                .map(move |p| (k, p, conjecture::N_from_kp(k, p)))
         })
         .collect();
 
+    let description = format!(
+        "test_kp {} {} blocks of {}",
+        max_k, max_p, KP_CHECKPOINT_BLOCK_SIZE
+    );
+    let mut checkpoint = checkpoint_mode.open(&description);
+
+    let find_solution = |N: Result<u128, utils::OutOfRange>| {
+        N.ok().and_then(|N| {
+            magic_squares::find_perfect_squares(&magic_squares::SearchOptions::default(), None, N)
+        })
+    };
+
     // Process each candidate.
-    let mut responses: Vec<(u64, u64, u64, Option<magic_squares::Solution>)> = candidates
-        .into_iter()
-        .map(|(k, p, N)| (k, p, N, magic_squares::find_perfect_squares(None, None, N)))
-        .collect();
+    let mut responses: Vec<(
+        u64,
+        u64,
+        Result<u128, utils::OutOfRange>,
+        Option<magic_squares::Solution>,
+    )> = match checkpoint.as_mut() {
+        None => candidates
+            .into_iter()
+            .map(|(k, p, N)| (k, p, N, find_solution(N)))
+            .collect(),
+        Some(checkpoint) => {
+            // Blocks are ranges of indices in `candidates`. The squares of a block are recorded with their N,
+            // and the candidates of a completed block without a square have none.
+            let mut responses = vec![];
+            for (index, block) in candidates
+                .chunks(KP_CHECKPOINT_BLOCK_SIZE as usize)
+                .enumerate()
+            {
+                let block_min = index as u64 * KP_CHECKPOINT_BLOCK_SIZE;
+                let block_max = block_min + block.len() as u64;
+                if let Some(records) = checkpoint.completed_block(block_min, block_max) {
+                    let solutions: Vec<magic_squares::Solution> = records
+                        .iter()
+                        .map(|record| {
+                            checkpoint::parse_solution_record(record).unwrap_or_else(|err| {
+                                eprintln!(
+                                    "Cannot resume from {}: {}",
+                                    checkpoint.path().display(),
+                                    err
+                                );
+                                std::process::exit(1);
+                            })
+                        })
+                        .collect();
+                    for &(k, p, N) in block {
+                        let sol = N
+                            .ok()
+                            .and_then(|N| solutions.iter().find(|sol| sol.N == N).cloned());
+                        responses.push((k, p, N, sol));
+                    }
+                    continue;
+                }
+
+                let found: Vec<_> = block
+                    .iter()
+                    .map(|&(k, p, N)| (k, p, N, find_solution(N)))
+                    .collect();
+                let records = found
+                    .iter()
+                    .filter_map(|(_, _, _, sol)| sol.as_ref().map(checkpoint::solution_record))
+                    .collect();
+                complete_checkpoint_block(checkpoint, block_min, block_max, records);
+                println!(
+                    "Candidates {}..{} of {} done ({:.2} seconds)",
+                    block_min,
+                    block_max,
+                    candidates.len(),
+                    start.elapsed().as_secs_f64()
+                );
+                responses.extend(found);
+            }
+            responses
+        }
+    };
 
     responses.sort_by_key(|(k, p, _N, _)| (*k, *p));
 
     for (k, p, N, sol_opt) in responses {
         println!("-----------------------");
+        let N = match N {
+            Ok(N) => N,
+            Err(err) => {
+                println!("⚠️ [p={}, k={}] N = (k*3*p²)² is {}", p, k, err);
+                continue;
+            }
+        };
         if let Some(sol) = sol_opt {
             if sol.solution_type == magic_squares::SolutionType::Perfect {
-                panic!(
-                    "😱 HONEY WAKE UP: perfect solution found for N = {}",
-                    N
-                );
+                report_perfect_solution(&sol);
+                continue;
             }
             println!(
-                "✅ [p={}, k={}] N = {} is a quasi magic square (incorrect axes = {:?})",
-                p, k, N, sol.incorrect_axis_values
+                "✅ [p={}, k={}] N = {} is a quasi magic square (incorrect axes = {})",
+                p,
+                k,
+                N,
+                sol.describe_incorrect_axes()
             );
             println!(
                 "a = {}, b = {}, c = {}, d = {}, e = {}, f = {}, g = {}, h = {}, i = {}",
@@ -135,20 +757,105 @@ fn test_kp_form_up_to(max_k: u64, max_p: u64) {
     println!("Time: {:.2} seconds", start.elapsed().as_secs_f64());
 }
 
+/// Largest n tested by `test_products` unless `--max-n` is given: the search for N = (3n)² = 9·10¹⁰ takes
+/// a few seconds.
+const DEFAULT_PRODUCTS_MAX_N: u64 = 100_000;
+
+/// Test all N = (3n)² where n is a product of up to `max_primes` distinct primes p ≡ 1 (mod 6) with p ≤ max_p,
+/// each with an exponent up to `max_exponent` (see `conjecture::products_of_6n1_primes`).
+/// For each N, reports how many quasi magic squares exist, and which prime factor each of them belongs to
+/// (see `conjecture::square_owners`).
+/// The search for each N takes a time proportional to √N = 3n without a table, so the products above `max_n` are
+/// skipped.
+fn test_prime_products(max_p: u64, max_exponent: u32, max_primes: usize, max_n: u64) {
+    let start = Instant::now();
+
+    let products =
+        conjecture::products_of_6n1_primes(max_p, max_exponent, max_primes, max_n as u128);
+    println!(
+        "Testing {} products of up to {} primes p ≡ 1 (mod 6) with p ≤ {} and exponents ≤ {}",
+        products.len(),
+        max_primes,
+        max_p,
+        max_exponent
+    );
+    println!(
+        "⚠️ Products n above {} are skipped, use --max-n to change this limit",
+        max_n
+    );
+
+    let responses: Vec<(Vec<(u64, u32)>, u128, Vec<magic_squares::Solution>)> = products
+        .into_par_iter()
+        .map(|factors| {
+            let n: u128 = factors
+                .iter()
+                .map(|&(p, exponent)| (p as u128).pow(exponent))
+                .product();
+            let N = 9 * n * n;
+            let sols = magic_squares::find_all_perfect_squares(
+                &magic_squares::SearchOptions::default(),
+                None,
+                N,
+            );
+            (factors, N, sols)
+        })
+        .collect();
+
+    for (factors, N, sols) in responses {
+        let n: Vec<String> = factors
+            .iter()
+            .map(|&(p, exponent)| match exponent {
+                1 => p.to_string(),
+                _ => format!("{}^{}", p, exponent),
+            })
+            .collect();
+        println!("------------------------------------------------------");
+        println!(
+            "N = (3*{})² = {} has {} quasi magic squares ({})",
+            n.join("*"),
+            N,
+            sols.len(),
+            conjecture::check_conjecture(N)
+        );
+        for sol in sols {
+            let owners: Vec<String> = conjecture::square_owners(&factors, &sol.grid())
+                .iter()
+                .map(|p| format!("p={}", p))
+                .collect();
+            println!(
+                "a = {}, b = {}, c = {}, d = {}, e = {}, f = {}, g = {}, h = {}, i = {} (incorrect axes = {}, belongs to {})",
+                sol.a, sol.b, sol.c, sol.d, sol.e, sol.f, sol.g, sol.h, sol.i, sol.describe_incorrect_axes(),
+                if owners.is_empty() { "no single prime".to_string() } else { owners.join(", ") }
+            );
+        }
+    }
+
+    println!("Time: {:.2} seconds", start.elapsed().as_secs_f64());
+}
+
 /// Generate a single large quasi magic square for the parameters (k, p).
+/// Only the square for k = 1 is searched, and then scaled by k (see `conjecture::construct_quasi_square`).
 fn generate_large_quasi_magic_square(k: u64, p: u64) {
     let start = Instant::now();
 
-    let N = (k * 3 * p.pow(2)).pow(2);
-    match magic_squares::find_perfect_squares(None, None, N) {
+    let N = match conjecture::N_from_kp(k, p) {
+        Ok(N) => N,
+        Err(err) => {
+            println!("⚠️ [p={}, k={}] N = (k*3*p²)² is {}", p, k, err);
+            return;
+        }
+    };
+    match conjecture::construct_quasi_square(k, p) {
+        Some(sol) if sol.solution_type == magic_squares::SolutionType::Perfect => {
+            report_perfect_solution(&sol);
+        }
         Some(sol) => {
-            if sol.solution_type == magic_squares::SolutionType::Perfect {
-                panic!("😱 HONEY WAKE UP: perfect solution found for N = {}", N);
-            }
-
             println!(
-                "✅ [p={}, k={}] N = {} is a quasi magic square (incorrect axes = {:?})",
-                p, k, N, sol.incorrect_axis_values
+                "✅ [p={}, k={}] N = {} is a quasi magic square (incorrect axes = {})",
+                p,
+                k,
+                N,
+                sol.describe_incorrect_axes()
             );
             println!(
                 "a = {}, b = {}, c = {}, d = {}, e = {}, f = {}, g = {}, h = {}, i = {}",
@@ -157,8 +864,8 @@ fn generate_large_quasi_magic_square(k: u64, p: u64) {
         }
         None => {
             println!(
-                "❌ N = {} is NOT a quasi magic square in the form of (k*3*p²)² with p={} and k={}",
-                N, p, k
+                "❌ There is no quasi magic square for k=1 and p={}, so none can be constructed for N = {}",
+                p, N
             );
         }
     }
@@ -166,13 +873,190 @@ fn generate_large_quasi_magic_square(k: u64, p: u64) {
     println!("Time: {:.2} seconds", start.elapsed().as_secs_f64());
 }
 
+/// Read a dataset written by `test_kp`, check that its squares scale linearly with k,
+/// and try to fit the entries of the squares for k = 1 by polynomials in p of degree at most `max_degree`.
+fn fit_dataset(path: &str, max_degree: usize) {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("Cannot read {}: {}", path, err);
+        std::process::exit(1);
+    });
+    let squares = parametrization::parse_dataset(&text).unwrap_or_else(|err| {
+        eprintln!("Cannot parse {}: {}", path, err);
+        std::process::exit(1);
+    });
+    let bases = parametrization::base_squares(&squares);
+    println!(
+        "Read {} squares from {}, {} of them for k = 1",
+        squares.len(),
+        path,
+        bases.len()
+    );
+
+    let not_scaling = parametrization::squares_not_scaling(&squares);
+    if not_scaling.is_empty() {
+        println!("✅ Every square for (k, p) is k times the square for (1, p)");
+    }
+    for square in not_scaling {
+        let sol = &square.solution;
+        println!(
+            "❌ [p={}, k={}] is not k times the square for (1, p) (incorrect axes = {})",
+            square.p,
+            square.k,
+            sol.describe_incorrect_axes()
+        );
+        println!(
+            "a = {}, b = {}, c = {}, d = {}, e = {}, f = {}, g = {}, h = {}, i = {}",
+            sol.a, sol.b, sol.c, sol.d, sol.e, sol.f, sol.g, sol.h, sol.i
+        );
+    }
+
+    println!("------------------------------------------------------");
+    for (entry, polynomial) in parametrization::fit_base_squares(&squares, max_degree) {
+        match polynomial {
+            Ok(Some(polynomial)) => println!("✅ {} = {}", entry, polynomial),
+            Ok(None) => println!(
+                "❌ {}: no polynomial in p of degree ≤ {} fits the {} squares for k = 1",
+                entry,
+                max_degree,
+                bases.len()
+            ),
+            Err(err) => println!(
+                "⚠️ {}: the exact arithmetic of the fit is {} (p is too large for a degree ≤ {})",
+                entry, err, max_degree
+            ),
+        }
+    }
+}
+
+/// Construct the quasi magic square for an arbitrarily large k (and p), verify it and print it in a verifiable format.
+/// When k can be factored, also count the squares with the same center, found with the big-integer decomposition.
+#[cfg(feature = "bigint")]
+fn generate_big_quasi_magic_square(k: &str, p: u64) {
+    let start = Instant::now();
+
+    let k: num_bigint::BigUint = k.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid argument for k");
+        std::process::exit(1);
+    });
+
+    match big_squares::construct_quasi_square(&k, p) {
+        Some(square) => {
+            match square.verify() {
+                Ok(()) => println!("# ✅ Verified ({} digits)", square.N.to_string().len()),
+                Err(err) => println!("# ❌ Verification failed: {}", err),
+            }
+            match big_squares::find_quasi_squares_with_center(&k, p) {
+                Some(squares) => println!(
+                    "# {} quasi magic squares with the same center (the one below and its symmetric variants included)",
+                    squares.len()
+                ),
+                None => println!("# k can't be factored: the squares with the same center were not searched"),
+            }
+            print!("{}", square.to_verifiable_string());
+        }
+        None => {
+            println!("❌ No quasi magic square found for k = 1 and p = {}", p);
+        }
+    }
+
+    println!("# Time: {:.2} seconds", start.elapsed().as_secs_f64());
+}
+
+/// Verify a square written by `generate_big`.
+#[cfg(feature = "bigint")]
+fn verify_big_quasi_magic_square(path: &str) {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("Cannot read {}: {}", path, err);
+        std::process::exit(1);
+    });
+    let result = big_squares::BigSolution::parse(&text).and_then(|square| square.verify());
+    match result {
+        Ok(()) => println!("✅ {} is a valid quasi magic square of squares", path),
+        Err(err) => {
+            println!(
+                "❌ {} is not a valid quasi magic square of squares: {}",
+                path, err
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
 const USAGE: &str = "
 Usage:
-    cargo test_n <n_min> <n_max>  # Test all N in the range [n_min, n_max)
-    cargo test_kp <max_k> <max_p> # Test all N in the form of (k*3*p²)² with k in [1, max_k] and p prime in [1, max_p]
-    cargo generate <k> <p>        # Generate a large quasi magic square in the form of (k*3*p²)²
+    cargo test_n <n_min> <n_max> [max_incorrect_axes] [--allow-repeats] [--allow-zero] [--segment=<size>] [--table=<file> [--verify-table]] [--checkpoint <file> | --resume <file>] [--counterexamples <file>] # Test all N in the range [n_min, n_max)
+    cargo precompute <n_max> <file> # Compute the sums of two squares up to n_max and write them to a table file for --table
+    cargo test_kp <max_k> <max_p> [--checkpoint <file> | --resume <file>] # Test all N in the form of (k*3*p²)² with k in [1, max_k] and p prime in [1, max_p]
+    cargo test_products <max_p> <max_exponent> [max_primes] [--max-n=<n>] # Test all N = (3n)² for n ≤ 100_000 (or --max-n) a product of up to max_primes (default 2) primes p ≡ 1 (mod 6) in [1, max_p], with exponents up to max_exponent
+    cargo benchmark_squares <n_min> <n_max> # Time is_perfect_square against the square lookup map it replaced, for all n in [n_min, n_max)
+    cargo hypotheses <n_min> <n_max> # Compare the conjecture and alternative hypotheses with the quasi magic squares for N in [n_min, n_max)
+    cargo generate <k> <p>        # Generate a large quasi magic square in the form of (k*3*p²)², by scaling the square for k = 1
+    cargo fit_dataset <file> [max_degree] # Check that the squares of a test_kp output scale with k, and fit their entries by polynomials in p (degree 4 by default)
+    cargo enumerate_n <n_min> <n_max> [max_incorrect_axes] [--allow-repeats] [--allow-zero] # List every square (not only the first) for all N in the range [n_min, n_max)
+    cargo grid <size> <N> [exponent] # Search all size x size magic squares of k-th powers (squares by default) with magic sum N
+    cargo semi_n <n_min> <n_max> [--allow-zero] # List every semi-magic square (rows and columns only) for all N in the range [n_min, n_max)
+    cargo generate_big <k> <p>    # Construct and verify the quasi magic square for (k*3*p²)² with an arbitrarily large k, and search the squares with the same center if k can be factored (requires --features bigint)
+    cargo verify_big <file>       # Verify a square written by generate_big (requires --features bigint)
+
+    max_incorrect_axes: maximum number of incorrect axes (0 to 3) for a square to be reported. Defaults to 1 (quasi magic squares).
+    --allow-repeats: also report squares whose 9 values are not all distinct (e.g. the Parker square).
+    --allow-zero: also report squares containing 0 (by default all values are strictly positive).
+    --segment=<size>: test N in blocks of <size> values, only precomputing the sums of two squares needed by each block.
+    --table=<file>: memory-map the sums of two squares from a file written by precompute, instead of computing them.
+    --verify-table: check the checksum of the whole table file before using it (only its header and size by default).
+    --checkpoint <file>: test in blocks, and record each completed block and its squares in <file>.
+    --resume <file>: continue an interrupted run from its checkpoint file, skipping the completed blocks.
+    --counterexamples <file>: where the quasi magic squares that do not fit the conjecture are written (counterexamples.txt by default).
 ";
 
+/// Removes the `--name=value` (or `--name value`) argument from `extra_args` and returns its value, if present.
+fn take_valued_option(extra_args: &mut Vec<String>, name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    if let Some(position) = extra_args.iter().position(|arg| arg.starts_with(&prefix)) {
+        return Some(extra_args.remove(position)[prefix.len()..].to_string());
+    }
+    let position = extra_args.iter().position(|arg| arg == name)?;
+    if position + 1 == extra_args.len() {
+        eprintln!("Missing value for {}", name);
+        std::process::exit(1);
+    }
+    extra_args.remove(position);
+    Some(extra_args.remove(position))
+}
+
+/// Parses the `--checkpoint <file>` and `--resume <file>` options of the long-running commands.
+fn take_checkpoint_mode(extra_args: &mut Vec<String>) -> CheckpointMode {
+    let create = take_valued_option(extra_args, "--checkpoint");
+    let resume = take_valued_option(extra_args, "--resume");
+    match (create, resume) {
+        (None, None) => CheckpointMode::None,
+        (Some(path), None) => CheckpointMode::Create(path),
+        (None, Some(path)) => CheckpointMode::Resume(path),
+        (Some(_), Some(_)) => {
+            eprintln!("--checkpoint and --resume cannot be combined");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parses the optional arguments of the search commands: `[max_incorrect_axes] [--allow-repeats] [--allow-zero]`.
+fn parse_search_options(extra_args: &[String]) -> magic_squares::SearchOptions {
+    let mut options = magic_squares::SearchOptions::default();
+    for arg in extra_args {
+        if arg == "--allow-repeats" {
+            options.allow_repeated_entries = true;
+        } else if arg == "--allow-zero" {
+            options.zero_policy = generate_squares::ZeroPolicy::NonNegative;
+        } else {
+            options.max_incorrect_axes = arg.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid argument for max_incorrect_axes");
+                std::process::exit(1);
+            });
+        }
+    }
+    options
+}
+
 fn main() {
     let mut args = std::env::args().skip(1);
 
@@ -181,13 +1065,64 @@ fn main() {
         std::process::exit(1);
     });
 
-    let arg1 = args
-        .next()
-        .unwrap_or_else(|| {
+    // File names are taken from `raw_arg1`, as underscores are only removed from numbers.
+    let raw_arg1 = args.next().unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    });
+    let arg1 = raw_arg1.replace('_', "");
+
+    // Commands with a file argument.
+    if command == "precompute" {
+        let n_max: u64 = arg1.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid argument for n_max");
+            std::process::exit(1);
+        });
+        let path = args.next().unwrap_or_else(|| {
             eprintln!("{}", USAGE);
             std::process::exit(1);
-        })
-        .replace('_', "");
+        });
+        precompute_table_file(n_max, &path);
+        return;
+    }
+    if command == "fit_dataset" {
+        let max_degree: usize = match args.next() {
+            Some(arg) => arg
+                .parse()
+                .ok()
+                .filter(|&degree| degree <= parametrization::MAX_FIT_DEGREE)
+                .unwrap_or_else(|| {
+                    eprintln!(
+                        "Invalid argument for max_degree (at most {})",
+                        parametrization::MAX_FIT_DEGREE
+                    );
+                    std::process::exit(1);
+                }),
+            None => 4,
+        };
+        fit_dataset(&raw_arg1, max_degree);
+        return;
+    }
+    // Commands whose arguments do not fit in a u64.
+    #[cfg(feature = "bigint")]
+    match command.as_str() {
+        "verify_big" => {
+            verify_big_quasi_magic_square(&raw_arg1);
+            return;
+        }
+        "generate_big" => {
+            let p: u64 = args
+                .next()
+                .and_then(|p| p.replace('_', "").parse().ok())
+                .unwrap_or_else(|| {
+                    eprintln!("Invalid argument for p");
+                    std::process::exit(1);
+                });
+            generate_big_quasi_magic_square(&arg1, p);
+            return;
+        }
+        _ => {}
+    }
     let arg2 = args
         .next()
         .unwrap_or_else(|| {
@@ -205,11 +1140,121 @@ fn main() {
         std::process::exit(1);
     });
 
+    // Remaining (optional) arguments, interpreted by each command.
+    let mut extra_args: Vec<String> = args.collect();
+
     match command.as_str() {
-        "test_n" => test_all_N_up_in_range(arg1, arg2, true),
-        "benchmark_n" => test_all_N_up_in_range(arg1, arg2, false),
-        "test_kp" => test_kp_form_up_to(arg1, arg2),
+        "test_n" | "benchmark_n" => {
+            // `--segment=<size>` tests the range in blocks of `size` values of N.
+            let segment_size = take_valued_option(&mut extra_args, "--segment").map(|size| {
+                match size.replace('_', "").parse::<u64>() {
+                    Ok(size) if size > 0 => size,
+                    _ => {
+                        eprintln!("Invalid argument for --segment (a positive block size)");
+                        std::process::exit(1);
+                    }
+                }
+            });
+            // `--table=<file>` maps a table written by `precompute` instead of computing it,
+            // and `--verify-table` checks all its data first.
+            let table_path = take_valued_option(&mut extra_args, "--table");
+            let verification = match extra_args.iter().position(|arg| arg == "--verify-table") {
+                Some(position) if table_path.is_some() => {
+                    extra_args.remove(position);
+                    table_file::Verification::Full
+                }
+                Some(_) => {
+                    eprintln!("--verify-table requires --table");
+                    std::process::exit(1);
+                }
+                None => table_file::Verification::Header,
+            };
+            // `--checkpoint <file>` records the completed blocks, `--resume <file>` skips them.
+            let checkpoint_mode = take_checkpoint_mode(&mut extra_args);
+            let counterexample_path = take_valued_option(&mut extra_args, "--counterexamples")
+                .unwrap_or_else(|| DEFAULT_COUNTEREXAMPLE_PATH.to_string());
+            let options = parse_search_options(&extra_args);
+            let multiprocessing = command == "test_n";
+            match segment_size {
+                Some(_) if table_path.is_some() => {
+                    eprintln!("--segment and --table cannot be combined");
+                    std::process::exit(1);
+                }
+                Some(_) if !matches!(checkpoint_mode, CheckpointMode::None) => {
+                    eprintln!("--segment cannot be combined with --checkpoint or --resume");
+                    std::process::exit(1);
+                }
+                Some(segment_size) => test_all_N_in_segments(
+                    arg1,
+                    arg2,
+                    segment_size,
+                    multiprocessing,
+                    options,
+                    &counterexample_path,
+                ),
+                None => test_all_N_up_in_range(
+                    arg1,
+                    arg2,
+                    multiprocessing,
+                    options,
+                    table_path.as_deref().map(|path| (path, verification)),
+                    checkpoint_mode,
+                    &counterexample_path,
+                ),
+            }
+        }
+        "test_kp" => {
+            let checkpoint_mode = take_checkpoint_mode(&mut extra_args);
+            test_kp_form_up_to(arg1, arg2, checkpoint_mode)
+        }
         "generate" => generate_large_quasi_magic_square(arg1, arg2),
+        "hypotheses" => compare_hypotheses(arg1, arg2),
+        "benchmark_squares" => benchmark_perfect_square(arg1, arg2),
+        "test_products" => {
+            let max_n = match take_valued_option(&mut extra_args, "--max-n") {
+                Some(max_n) => max_n.replace('_', "").parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid argument for --max-n");
+                    std::process::exit(1);
+                }),
+                None => DEFAULT_PRODUCTS_MAX_N,
+            };
+            let max_primes: usize = match extra_args.first() {
+                Some(arg) => arg.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid argument for max_primes");
+                    std::process::exit(1);
+                }),
+                None => 2,
+            };
+            let max_exponent = u32::try_from(arg2).unwrap_or_else(|_| {
+                eprintln!("Invalid argument for max_exponent");
+                std::process::exit(1);
+            });
+            test_prime_products(arg1, max_exponent, max_primes, max_n)
+        }
+        "grid" => {
+            let exponent: u32 = match extra_args.first() {
+                Some(arg) => arg.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid argument for exponent");
+                    std::process::exit(1);
+                }),
+                None => 2,
+            };
+            search_magic_grids(arg1, arg2, exponent)
+        }
+        "semi_n" => {
+            let zero_policy = match extra_args.as_slice() {
+                [] => generate_squares::ZeroPolicy::StrictlyPositive,
+                [arg] if arg == "--allow-zero" => generate_squares::ZeroPolicy::NonNegative,
+                _ => {
+                    eprintln!("{}", USAGE);
+                    std::process::exit(1);
+                }
+            };
+            semi_magic_squares_in_range(arg1, arg2, zero_policy)
+        }
+        "enumerate_n" => {
+            enumerate_all_squares_in_range(arg1, arg2, parse_search_options(&extra_args))
+        }
         _ => {
             eprintln!("{}", USAGE);
             std::process::exit(1);
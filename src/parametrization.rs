@@ -0,0 +1,298 @@
+//! Looks for closed-form parametrisations of the quasi magic squares in a dataset written by `test_kp`
+//! (e.g. `quasi_magic_squares_k10_p199.txt`): checks that the square for (k, p) is k times the square for (1, p),
+//! and tries to fit each entry of the squares for k = 1 by a polynomial in p with rational coefficients.
+
+use std::fmt;
+
+use fxhash::FxHashMap;
+use num_rational::Ratio;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub};
+
+use crate::conjecture;
+use crate::magic_squares::Solution;
+use crate::utils::OutOfRange;
+
+/// A quasi magic square read from a dataset, with its parameters.
+pub struct DatasetSquare {
+    pub k: u64,
+    pub p: u64,
+    pub solution: Solution,
+}
+
+/// Parses the squares printed by `test_kp`: a `✅ [p=<p>, k=<k>] N = ...` line followed by an `a = ..., i = ...` line.
+/// Other lines are ignored.
+pub fn parse_dataset(text: &str) -> Result<Vec<DatasetSquare>, String> {
+    let mut squares = vec![];
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let Some(parameters) = line
+            .strip_prefix("✅ [")
+            .and_then(|rest| rest.split(']').next())
+        else {
+            continue;
+        };
+        let invalid = || format!("invalid line `{}`", line);
+        let (mut k, mut p) = (None, None);
+        for parameter in parameters.split(", ") {
+            match parameter.split_once('=') {
+                Some(("k", value)) => k = value.parse().ok(),
+                Some(("p", value)) => p = value.parse().ok(),
+                _ => return Err(invalid()),
+            }
+        }
+        let (k, p) = k.zip(p).ok_or_else(invalid)?;
+
+        let cells_line = lines.next().ok_or_else(invalid)?;
+        let cells: Vec<u64> = cells_line
+            .split(", ")
+            .map(|cell| {
+                cell.split_once(" = ")
+                    .and_then(|(_, value)| value.parse().ok())
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("invalid line `{}`", cells_line))?;
+        let grid: [u64; 9] = cells
+            .try_into()
+            .map_err(|_| format!("invalid line `{}`", cells_line))?;
+        let N = conjecture::N_from_kp(k, p)
+            .map_err(|err| format!("[p={}, k={}]: N is {}", p, k, err))?;
+        squares.push(DatasetSquare {
+            k,
+            p,
+            solution: Solution::from_grid(N, grid),
+        });
+    }
+    Ok(squares)
+}
+
+/// The squares for k = 1, by p, in their canonical orientation (see `Solution::canonical_grid`).
+pub fn base_squares(squares: &[DatasetSquare]) -> FxHashMap<u64, [u64; 9]> {
+    squares
+        .iter()
+        .filter(|square| square.k == 1)
+        .map(|square| (square.p, square.solution.canonical_grid()))
+        .collect()
+}
+
+/// Returns the squares that are not k times the square for (1, p), up to rotations and reflections
+/// (including those whose p has no square for k = 1 in the dataset).
+pub fn squares_not_scaling<'a>(squares: &'a [DatasetSquare]) -> Vec<&'a DatasetSquare> {
+    let bases = base_squares(squares);
+    squares
+        .iter()
+        .filter(|square| match bases.get(&square.p) {
+            Some(base) => {
+                let scaled = Solution::from_grid(square.solution.N, base.map(|x| x * square.k));
+                scaled.canonical_grid() != square.solution.canonical_grid()
+            }
+            None => true,
+        })
+        .collect()
+}
+
+/// Largest degree that can be fitted.
+pub const MAX_FIT_DEGREE: usize = 6;
+
+/// A polynomial in p with rational coefficients, from the constant term up.
+///
+/// The coefficients are exact fractions of i128. The Lagrange denominators and the powers of p grow like p^degree,
+/// so all the arithmetic is checked: a computation that would overflow fails with `OutOfRange`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Polynomial(pub Vec<Ratio<i128>>);
+
+impl Polynomial {
+    pub fn eval(&self, p: i128) -> Result<Ratio<i128>, OutOfRange> {
+        let p = Ratio::from_integer(p);
+        self.0
+            .iter()
+            .rev()
+            .try_fold(Ratio::from_integer(0), |acc, c| {
+                acc.checked_mul(&p)?.checked_add(c)
+            })
+            .ok_or(OutOfRange)
+    }
+
+    /// The interpolating polynomial of the points, of degree below their number (Lagrange's formula).
+    fn interpolate(points: &[(i128, i128)]) -> Result<Polynomial, OutOfRange> {
+        let mut coefficients = vec![Ratio::from_integer(0); points.len()];
+        for (j, &(x_j, y_j)) in points.iter().enumerate() {
+            // y_j * Π_{m ≠ j} (p - x_m) / (x_j - x_m), expanded term by term.
+            let mut basis = vec![Ratio::from_integer(y_j)];
+            for (m, &(x_m, _)) in points.iter().enumerate() {
+                if m == j {
+                    continue;
+                }
+                let scale = Ratio::from_integer(x_j.checked_sub(x_m).ok_or(OutOfRange)?);
+                let x_m = Ratio::from_integer(x_m);
+                let mut next = vec![Ratio::from_integer(0); basis.len() + 1];
+                for (d, c) in basis.iter().enumerate() {
+                    let c = c.checked_div(&scale).ok_or(OutOfRange)?;
+                    next[d + 1] = next[d + 1].checked_add(&c).ok_or(OutOfRange)?;
+                    next[d] = c
+                        .checked_mul(&x_m)
+                        .and_then(|term| next[d].checked_sub(&term))
+                        .ok_or(OutOfRange)?;
+                }
+                basis = next;
+            }
+            for (c, b) in coefficients.iter_mut().zip(basis) {
+                *c = c.checked_add(&b).ok_or(OutOfRange)?;
+            }
+        }
+        while coefficients.len() > 1 && coefficients.last() == Some(&Ratio::from_integer(0)) {
+            coefficients.pop();
+        }
+        Ok(Polynomial(coefficients))
+    }
+}
+
+impl fmt::Display for Polynomial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terms: Vec<String> = self
+            .0
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, c)| **c != Ratio::from_integer(0))
+            .map(|(degree, c)| match degree {
+                0 => format!("{}", c),
+                1 => format!("({})*p", c),
+                _ => format!("({})*p^{}", c, degree),
+            })
+            .collect();
+        if terms.is_empty() {
+            return write!(f, "0");
+        }
+        write!(f, "{}", terms.join(" + "))
+    }
+}
+
+/// Returns the polynomial of lowest degree (at most `max_degree`) going through all the points,
+/// interpolated on the first ones and checked on the others. A polynomial is only accepted if at least
+/// one point is left to check it, so that any set of points is not trivially "fitted".
+/// Fails with `OutOfRange` if the exact arithmetic overflows before a polynomial is found (e.g. with large p).
+pub fn fit_polynomial(
+    points: &[(i128, i128)],
+    max_degree: usize,
+) -> Result<Option<Polynomial>, OutOfRange> {
+    assert!(max_degree <= MAX_FIT_DEGREE);
+    for degree in (0..=max_degree).take_while(|degree| degree + 1 < points.len()) {
+        let polynomial = Polynomial::interpolate(&points[..=degree])?;
+        let mut fits = true;
+        for &(x, y) in points {
+            if polynomial.eval(x)? != Ratio::from_integer(y) {
+                fits = false;
+                break;
+            }
+        }
+        if fits {
+            return Ok(Some(polynomial));
+        }
+    }
+    Ok(None)
+}
+
+/// Tries to fit each entry of the squares for k = 1 as a polynomial in p, with the entries taken either in the
+/// canonical orientation (cells a to i), or sorted by increasing value (which does not depend on the orientation).
+/// Returns the label of each entry with its polynomial, if one fits (see `fit_polynomial`).
+pub fn fit_base_squares(
+    squares: &[DatasetSquare],
+    max_degree: usize,
+) -> Vec<(String, Result<Option<Polynomial>, OutOfRange>)> {
+    let mut bases: Vec<(u64, [u64; 9])> = base_squares(squares).into_iter().collect();
+    bases.sort_unstable_by_key(|&(p, _)| p);
+
+    let mut fits = vec![];
+    for (label, sorted) in [("cell", false), ("sorted entry", true)] {
+        for index in 0..9 {
+            let points: Vec<(i128, i128)> = bases
+                .iter()
+                .map(|&(p, mut grid)| {
+                    if sorted {
+                        grid.sort_unstable();
+                    }
+                    (p as i128, grid[index] as i128)
+                })
+                .collect();
+            let name = if sorted {
+                format!("{} {}", label, index + 1)
+            } else {
+                format!("{} {}", label, (b'a' + index as u8) as char)
+            };
+            fits.push((name, fit_polynomial(&points, max_degree)));
+        }
+    }
+    fits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_polynomial() {
+        // 3/2 p² - p + 13/2, on primes.
+        let points: Vec<(i128, i128)> = [7i128, 13, 19, 31, 37]
+            .iter()
+            .map(|&p| (p, (3 * p * p - 2 * p + 13) / 2))
+            .collect();
+        let polynomial = fit_polynomial(&points, 4).unwrap().unwrap();
+        assert_eq!(
+            polynomial.0,
+            vec![Ratio::new(13, 2), Ratio::from_integer(-1), Ratio::new(3, 2)]
+        );
+        assert_eq!(polynomial.to_string(), "(3/2)*p^2 + (-1)*p + 13/2");
+        // Not enough points to check a polynomial of degree 4.
+        assert_eq!(fit_polynomial(&points[..3], 4), Ok(None));
+        assert_eq!(
+            fit_polynomial(&[(1, 1), (2, 4), (3, 9), (4, 17)], 2),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_fit_polynomial_overflow() {
+        // Entries that no polynomial fits, on large primes: the powers of p overflow an i128 before the degree limit.
+        let points: Vec<(i128, i128)> = [
+            1_000_000_007i128,
+            1_000_000_009,
+            1_000_000_021,
+            1_000_000_033,
+            1_000_000_087,
+            1_000_000_093,
+            1_000_000_097,
+            1_000_000_103,
+        ]
+        .iter()
+        .enumerate()
+        .map(|(index, &p)| (p, (index as i128) % 2))
+        .collect();
+        assert_eq!(fit_polynomial(&points, MAX_FIT_DEGREE), Err(OutOfRange));
+    }
+
+    #[test]
+    fn test_parse_dataset() {
+        let text = "-----------------------
+✅ [p=7, k=1] N = 21609 is a quasi magic square in the form of (k*3*p²)² (incorrect axes = [10092])
+a = 74, b = 127, c = 2, d = 97, e = 58, f = 94, g = 82, h = 46, i = 113
+-----------------------
+✅ [p=7, k=2] N = 86436 is a quasi magic square in the form of (k*3*p²)² (incorrect axes = [40368])
+a = 148, b = 254, c = 4, d = 194, e = 116, f = 188, g = 164, h = 92, i = 226
+-----------------------
+✅ [p=7, k=3] N = 194481 is a quasi magic square in the form of (k*3*p²)² (incorrect axes = [90828])
+a = 222, b = 381, c = 6, d = 291, e = 174, f = 282, g = 246, h = 138, i = 33
+";
+        let squares = parse_dataset(text).unwrap();
+        assert_eq!(squares.len(), 3);
+        assert_eq!((squares[1].k, squares[1].p), (2, 7));
+        assert_eq!(squares[1].solution.incorrect_axes.len(), 1);
+
+        // The last square has a wrong entry, which breaks the 3 axes of i on top of the anti-diagonal.
+        let not_scaling = squares_not_scaling(&squares);
+        assert_eq!(not_scaling.len(), 1);
+        assert_eq!(not_scaling[0].k, 3);
+        assert_eq!(not_scaling[0].solution.incorrect_axes.len(), 4);
+
+        assert!(parse_dataset("✅ [p=7, k=1] N = 21609\na = 74, b = 127").is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! Versioned binary file format for the sum-of-two-squares table (see `generate_squares::SquareSumTable`),
+//! so that it can be computed once by the `precompute` command and memory-mapped by later runs.
+//!
+//! Layout (all integers are little-endian):
+//!
+//! | bytes  | content                                                    |
+//! |--------|------------------------------------------------------------|
+//! | 0..8   | magic `MSQTABLE`                                           |
+//! | 8..12  | format version (`VERSION`)                                 |
+//! | 12..16 | reserved (0)                                               |
+//! | 16..24 | min_n                                                      |
+//! | 24..32 | max_n                                                      |
+//! | 32..40 | number of offsets (max_n - min_n + 2)                      |
+//! | 40..48 | number of pairs                                            |
+//! | 48..56 | checksum of everything after the header                    |
+//! | 56..64 | reserved (0)                                               |
+//! | 64..   | offsets (u32), padded to 8 bytes, then pairs (2 × u32)     |
+//!
+//! Reading the whole file to verify its checksum takes longer than mapping it, so it is only done on request
+//! (see `Verification`).
+//!
+//! Perfect squares are tested arithmetically (see `generate_squares::is_perfect_square`), so they are not stored.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::generate_squares::SquareSumTable;
+
+const MAGIC: &[u8; 8] = b"MSQTABLE";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 64;
+/// Position of the checksum in the header.
+const CHECKSUM_POSITION: usize = 48;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a over 64-bit words (the data is always a multiple of 8 bytes long).
+fn checksum(data: &[u8]) -> u64 {
+    data.chunks_exact(8).fold(FNV_OFFSET_BASIS, |hash, word| {
+        (hash ^ u64::from_le_bytes(word.try_into().unwrap())).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Forwards the data to `inner`, and computes its `checksum` on the way.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hash: u64,
+    /// Bytes of the current (incomplete) 64-bit word.
+    word: [u8; 8],
+    word_len: usize,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        ChecksumWriter {
+            inner,
+            hash: FNV_OFFSET_BASIS,
+            word: [0; 8],
+            word_len: 0,
+        }
+    }
+
+    /// Returns the writer and the checksum of all the data written.
+    fn finish(self) -> (W, u64) {
+        debug_assert_eq!(self.word_len, 0, "the data is not a multiple of 8 bytes");
+        (self.inner, self.hash)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        for &byte in &buf[..written] {
+            self.word[self.word_len] = byte;
+            self.word_len += 1;
+            if self.word_len == 8 {
+                self.hash = (self.hash ^ u64::from_le_bytes(self.word)).wrapping_mul(FNV_PRIME);
+                self.word_len = 0;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes the table to `path`. The arrays are streamed to the file, and the checksum is written in the header
+/// once they are all written.
+pub fn save(table: &SquareSumTable, path: &Path) -> io::Result<()> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    for value in [
+        table.min_n(),
+        table.max_n(),
+        table.offsets().len() as u64,
+        table.pairs().len() as u64,
+        // Checksum, filled in at the end.
+        0,
+        0,
+    ] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&header)?;
+
+    // The offsets are padded to a multiple of 8 bytes.
+    let mut data = ChecksumWriter::new(file);
+    for &offset in table.offsets() {
+        data.write_all(&offset.to_le_bytes())?;
+    }
+    if table.offsets().len() % 2 != 0 {
+        data.write_all(&[0; 4])?;
+    }
+    for &[x, y] in table.pairs() {
+        data.write_all(&x.to_le_bytes())?;
+        data.write_all(&y.to_le_bytes())?;
+    }
+    let (mut file, checksum) = data.finish();
+
+    file.seek(SeekFrom::Start(CHECKSUM_POSITION as u64))?;
+    file.write_all(&checksum.to_le_bytes())?;
+    file.flush()
+}
+
+/// How much of a table file `load` checks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// The header and the size of the file: only a few bytes are read.
+    Header,
+    /// Also the checksum of the data and the order of the offsets, which reads the whole file.
+    Full,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Memory-maps a table written by `save`, after checking its header and size (and its data, depending on
+/// `verification`). Without the full verification, a corrupted table can make lookups panic, but not read
+/// out of the file.
+pub fn load(path: &Path, verification: Verification) -> io::Result<SquareSumTable> {
+    if cfg!(target_endian = "big") {
+        return Err(invalid_data(
+            "table files can only be mapped on little-endian machines".to_string(),
+        ));
+    }
+
+    let file = File::open(path)?;
+    // SAFETY: the file is only read, and is not expected to be modified while the program runs.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+
+    if map.len() < HEADER_SIZE || &map[0..8] != MAGIC {
+        return Err(invalid_data(format!(
+            "{} is not a table file",
+            path.display()
+        )));
+    }
+    let read_u64 = |at: usize| u64::from_le_bytes(map[at..at + 8].try_into().unwrap());
+    let version = u32::from_le_bytes(map[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(invalid_data(format!(
+            "unsupported table file version {} (expected {})",
+            version, VERSION
+        )));
+    }
+    let (min_n, max_n) = (read_u64(16), read_u64(24));
+    let (offset_count, pair_count) = (read_u64(32), read_u64(40));
+
+    // Sizes are checked against the header and the file length before any array is accessed.
+    let truncated = || invalid_data(format!("{} is truncated or corrupted", path.display()));
+    // A table always has at least its final offset.
+    if offset_count == 0
+        || max_n.checked_add(2).and_then(|m| m.checked_sub(min_n)) != Some(offset_count)
+    {
+        return Err(truncated());
+    }
+    let offsets_size = offset_count.checked_mul(4).ok_or_else(truncated)? as usize;
+    let pairs_size = pair_count.checked_mul(8).ok_or_else(truncated)? as usize;
+    let pairs_start = HEADER_SIZE + offsets_size.next_multiple_of(8);
+    if pairs_start.checked_add(pairs_size) != Some(map.len()) {
+        return Err(truncated());
+    }
+    let offsets = HEADER_SIZE..HEADER_SIZE + offsets_size;
+    let pairs = pairs_start..map.len();
+    if verification == Verification::Full
+        && checksum(&map[HEADER_SIZE..]) != read_u64(CHECKSUM_POSITION)
+    {
+        return Err(invalid_data(format!(
+            "checksum mismatch in {}",
+            path.display()
+        )));
+    }
+
+    let table = SquareSumTable::from_mapped(min_n, map, offsets, pairs);
+    let offsets = table.offsets();
+    if offsets[0] != 0
+        || offsets[offsets.len() - 1] as u64 != pair_count
+        || (verification == Verification::Full && offsets.windows(2).any(|w| w[0] > w[1]))
+    {
+        return Err(invalid_data(format!(
+            "invalid offsets in {}",
+            path.display()
+        )));
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_squares;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("msq_table_{}.bin", std::process::id()));
+        for table in [
+            generate_squares::generate_squares_sum_fast(10_000).unwrap(),
+            generate_squares::generate_squares_sum_window(5_000, 5_002).unwrap(),
+        ] {
+            save(&table, &path).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            assert_eq!(
+                bytes[CHECKSUM_POSITION..CHECKSUM_POSITION + 8],
+                checksum(&bytes[HEADER_SIZE..]).to_le_bytes()
+            );
+            let loaded = load(&path, Verification::Full).unwrap();
+            assert!(loaded.is_mapped());
+            assert_eq!(
+                (loaded.min_n(), loaded.max_n()),
+                (table.min_n(), table.max_n())
+            );
+            assert_eq!(loaded.offsets(), table.offsets());
+            assert_eq!(loaded.pairs(), table.pairs());
+        }
+
+        // Any modified byte of the data is detected by the full verification only.
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(load(&path, Verification::Header).is_ok());
+        assert!(load(&path, Verification::Full).is_err());
+
+        // A truncated file is always detected.
+        std::fs::write(&path, &bytes[..bytes.len() - 8]).unwrap();
+        assert!(load(&path, Verification::Header).is_err());
+
+        // So is a header without any offset (min_n = max_n + 2), which is consistent with an empty file.
+        let mut header = bytes[..HEADER_SIZE].to_vec();
+        header[16..24].copy_from_slice(&7u64.to_le_bytes());
+        header[24..32].copy_from_slice(&5u64.to_le_bytes());
+        header[32..48].fill(0);
+        std::fs::write(&path, &header).unwrap();
+        assert!(load(&path, Verification::Header).is_err());
+        assert!(load(&path, Verification::Full).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,17 +1,21 @@
 use fxhash::FxHashMap;
+use std::sync::OnceLock;
+
+use crate::factorization;
+use crate::generate_squares::SquarePair;
 
 /// Returns the “bit‐length” of x (i.e. ⌊log₂(x)⌋+1).
-fn bit_length(x: u64) -> u32 {
+fn bit_length(x: u128) -> u32 {
     if x == 0 {
         0
     } else {
-        64 - x.leading_zeros()
+        128 - x.leading_zeros()
     }
 }
 
 /// Verifies if a number can be written as the sum of three squares. This is known as the Legendre's three-square theorem, and the A004215 sequence in OEIS.
-pub fn can_be_written_as_sum_of_three_squares(n: u64) -> bool {
-    let m = bit_length((!n) & (n - 1));
+pub fn can_be_written_as_sum_of_three_squares(n: u128) -> bool {
+    let m = bit_length((!n) & n.wrapping_sub(1));
     if m == 0 && ((n >> m) & 7 == 7) {
         false
     } else {
@@ -19,8 +23,58 @@ pub fn can_be_written_as_sum_of_three_squares(n: u64) -> bool {
     }
 }
 
-/// Check if a number is prime
+/// Error returned when a value does not fit in the range supported by the computation,
+/// instead of silently wrapping around.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfRange;
+
+impl std::fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "out of range")
+    }
+}
+
+/// Numbers below this bound are tested with a sieve, computed once.
+const PRIME_SIEVE_LIMIT: u64 = 1 << 22;
+
+/// Bitset of the odd primes below `PRIME_SIEVE_LIMIT` (bit k stands for 2k + 1).
+fn prime_sieve() -> &'static [u64] {
+    static SIEVE: OnceLock<Vec<u64>> = OnceLock::new();
+    SIEVE.get_or_init(|| {
+        let half = (PRIME_SIEVE_LIMIT / 2) as usize;
+        let mut sieve = vec![u64::MAX; half / 64];
+        sieve[0] &= !1; // 1 is not prime.
+        let mut p = 3;
+        while p * p < PRIME_SIEVE_LIMIT as usize {
+            if sieve[p / 128] >> ((p / 2) % 64) & 1 == 1 {
+                for multiple in (p * p..PRIME_SIEVE_LIMIT as usize).step_by(2 * p) {
+                    sieve[multiple / 128] &= !(1 << ((multiple / 2) % 64));
+                }
+            }
+            p += 2;
+        }
+        sieve
+    })
+}
+
+/// Check if a number is prime: a sieve lookup for small numbers, then a deterministic Miller–Rabin test.
 pub fn is_prime(n: u64) -> bool {
+    is_prime_u128(n as u128)
+}
+
+/// Same as `is_prime`, for numbers that may not fit in a u64.
+/// Miller–Rabin is deterministic below 3.3·10^24 (so for every u64), and probabilistic above.
+pub fn is_prime_u128(n: u128) -> bool {
+    if n < PRIME_SIEVE_LIMIT as u128 {
+        let n = n as usize;
+        return n == 2 || (n % 2 == 1 && prime_sieve()[n / 128] >> ((n / 2) % 64) & 1 == 1);
+    }
+    factorization::is_probable_prime(n)
+}
+
+/// Check if a number is prime by trial division.
+/// Kept as a reference implementation for `is_prime`.
+pub fn is_prime_trial_division(n: u64) -> bool {
     if n < 2 {
         return false;
     }
@@ -43,7 +97,10 @@ pub fn is_prime(n: u64) -> bool {
 }
 
 /// Given a slice `lst`, returns all non-symmetric 3‑element combinations.
-pub fn nonsymetric_permutations_3(lst: &[(u64, u64)]) -> Vec<((u64, u64), (u64, u64), (u64, u64))> {
+/// The pairs are widened to u64 as they are picked (see `SquarePair`).
+pub fn nonsymetric_permutations_3<P: SquarePair>(
+    lst: &[P],
+) -> Vec<((u64, u64), (u64, u64), (u64, u64))> {
     let n = lst.len();
     // Preallocate the vector with the final size:
     let mut results = Vec::with_capacity(n * (n - 1) * (n - 2) / 2);
@@ -59,7 +116,7 @@ pub fn nonsymetric_permutations_3(lst: &[(u64, u64)]) -> Vec<((u64, u64), (u64,
                 if k == i {
                     continue;
                 }
-                results.push((lst[i], lst[j], lst[k]));
+                results.push((lst[i].widen(), lst[j].widen(), lst[k].widen()));
             }
         }
     }
@@ -67,13 +124,66 @@ pub fn nonsymetric_permutations_3(lst: &[(u64, u64)]) -> Vec<((u64, u64), (u64,
     results
 }
 
+/// Same as `nonsymetric_permutations_3`, but the same element can be picked several times.
+/// Used when the 9 values of the square are not required to be distinct.
+pub fn nonsymetric_permutations_with_repetition_3<P: SquarePair>(
+    lst: &[P],
+) -> Vec<((u64, u64), (u64, u64), (u64, u64))> {
+    let n = lst.len();
+    let mut results = Vec::with_capacity(n * n * (n + 1) / 2);
+
+    for i in 0..n {
+        for j in 0..n {
+            for k in j..n {
+                results.push((lst[i].widen(), lst[j].widen(), lst[k].widen()));
+            }
+        }
+    }
+
+    results
+}
+
+/// Returns all the permutations of `values` (n! permutations for n values).
+pub fn permutations<T: Copy>(values: &[T]) -> Vec<Vec<T>> {
+    if values.is_empty() {
+        return vec![vec![]];
+    }
+
+    let mut results = vec![];
+    for i in 0..values.len() {
+        let mut rest = values.to_vec();
+        let first = rest.remove(i);
+        for mut perm in permutations(&rest) {
+            perm.insert(0, first);
+            results.push(perm);
+        }
+    }
+    results
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_prime_matches_trial_division() {
+        let windows = [
+            0..100_000,
+            PRIME_SIEVE_LIMIT - 10_000..PRIME_SIEVE_LIMIT + 10_000,
+            1_000_000_000_000..1_000_000_010_000,
+        ];
+        for n in windows.into_iter().flatten() {
+            assert_eq!(is_prime(n), is_prime_trial_division(n), "n = {}", n);
+        }
+        // Largest prime below 2^64, and a strong pseudoprime to the bases 2, 3, 5 and 7.
+        assert!(is_prime(18_446_744_073_709_551_557));
+        assert!(!is_prime(3_215_031_751));
+        assert!(is_prime_u128((1 << 89) - 1));
+    }
+
     #[test]
     fn test_nonsymetric_permutations_3() {
-        let input_4 = &[(0, 0), (1, 1), (2, 2), (3, 3)];
+        let input_4: &[(u64, u64)] = &[(0, 0), (1, 1), (2, 2), (3, 3)];
         let expected_4 = vec![
             ((0, 0), (1, 1), (2, 2)),
             ((0, 0), (1, 1), (3, 3)),
@@ -95,7 +205,7 @@ mod tests {
         expected_4.sort();
         assert_eq!(result_4, expected_4);
 
-        let input_3 = &[(10, 10), (20, 20), (30, 30)];
+        let input_3: &[(u64, u64)] = &[(10, 10), (20, 20), (30, 30)];
         let expected_3 = vec![
             ((10, 10), (20, 20), (30, 30)),
             ((20, 20), (10, 10), (30, 30)),
@@ -108,4 +218,20 @@ mod tests {
         expected_3.sort();
         assert_eq!(result_3, expected_3);
     }
+
+    #[test]
+    fn test_nonsymetric_permutations_with_repetition_3() {
+        let input: &[(u64, u64)] = &[(0, 0), (1, 1)];
+        let mut result = nonsymetric_permutations_with_repetition_3(input);
+        result.sort();
+        let expected = vec![
+            ((0, 0), (0, 0), (0, 0)),
+            ((0, 0), (0, 0), (1, 1)),
+            ((0, 0), (1, 1), (1, 1)),
+            ((1, 1), (0, 0), (0, 0)),
+            ((1, 1), (0, 0), (1, 1)),
+            ((1, 1), (1, 1), (1, 1)),
+        ];
+        assert_eq!(result, expected);
+    }
 }